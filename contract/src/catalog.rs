@@ -0,0 +1,24 @@
+//! The question catalog answers are encoded against.
+//!
+//! Answer `a` to question `q` is encoded as `q * ANSWERS_PER_QUESTION + a`, and a
+//! user's answers are listed in question order, so the PSI compares the i-th
+//! answer of both parties. Only encodings of the catalog's answers are accepted,
+//! which stops a client from probing the other party with arbitrary values.
+
+/// Number of questions the frontend asks
+pub const QUESTION_COUNT: u128 = 12;
+pub const ANSWERS_PER_QUESTION: u128 = 5;
+
+pub fn encode_answer(question: u128, answer: u128) -> u128 {
+    question * ANSWERS_PER_QUESTION + answer
+}
+
+/// Whether `value` encodes one of the answers to the `position`-th question.
+pub fn is_valid_answer(position: usize, value: u128) -> bool {
+    (position as u128) < QUESTION_COUNT && value / ANSWERS_PER_QUESTION == position as u128
+}
+
+/// Whether `values` are valid answers to the first `values.len()` questions.
+pub fn are_valid_answers(values: &[u128]) -> bool {
+    values.iter().enumerate().all(|(i, v)| is_valid_answer(i, *v))
+}
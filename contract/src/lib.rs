@@ -1,9 +1,60 @@
+use std::collections::BTreeMap;
+
 use borsh::{io::Error, BorshDeserialize, BorshSerialize};
 use serde::{forward_to_deserialize_any, Deserialize, Serialize};
 
 use sdk::{Digestable, HyleContract, RunResult};
 use sha2::{Digest, Sha256};
 
+use crypto::blinding::{blinded_differences, is_unit};
+use crypto::{Blinding, HomomorphicScheme, Packing};
+
+pub mod catalog;
+pub mod scheme;
+pub mod state;
+
+pub use scheme::{Ciphertexts, PublicKey, SchemeKind, TaggedScheme};
+pub use state::{StateError, STATE_VERSION};
+
+/// Interest encodings (see `catalog::encode_answer`) must fit in this many bits,
+/// which fixes the packing layout of encrypted interests.
+pub const INTEREST_BITS: u32 = 6;
+
+/// Layout of encrypted interests: one answer per ciphertext, since the server
+/// blinds every difference on its own (see `crypto::blinding`).
+pub fn interest_packing() -> Packing {
+    Packing::single(INTEREST_BITS)
+}
+
+/// How an `AddEncryption` lays its interests out in plaintexts
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InterestLayout {
+    /// `interest_packing()`, which queries answered with `BlindDifferences` need
+    #[default]
+    Single,
+    /// As many interests per ciphertext as the key's plaintexts hold, for PSIs
+    /// whose differences only the key holder sees, unblinded
+    Dense,
+}
+
+impl InterestLayout {
+    pub fn packing<S: HomomorphicScheme>(&self, pk: &S::PublicKey) -> Packing {
+        match self {
+            InterestLayout::Single => interest_packing(),
+            InterestLayout::Dense => Packing::for_key::<S>(pk, INTEREST_BITS),
+        }
+    }
+}
+
+/// Space separated interests, the private input of `MeetupAction::PostRoot`
+fn parse_interests(private_input: &[u8]) -> Result<Vec<u128>, String> {
+    let data = core::str::from_utf8(private_input).map_err(|e| format!("Interests are not UTF-8: {}", e))?;
+    data.split(' ')
+        .map(|x| x.parse().map_err(|_| format!("Interest {:?} is not a number", x)))
+        .collect()
+}
+
 
 impl HyleContract for Meetup {
     /// Entry point of the contract's logic
@@ -11,103 +62,218 @@ impl HyleContract for Meetup {
         // Parse contract inputs
         let (action, ctx) = sdk::utils::parse_raw_contract_input::<MeetupAction>(contract_input)?;
 
+        if self.version != STATE_VERSION && !matches!(action, MeetupAction::Migrate) {
+            return Err(format!(
+                "The state is in layout v{}, send Migrate to upgrade it to v{} first",
+                self.version, STATE_VERSION
+            ));
+        }
+
         // Execute the contract logic
-        match action {
+        let event = match action {
+            MeetupAction::Migrate => {
+                if self.version == STATE_VERSION {
+                    return Err(format!("The state is already in layout v{}", STATE_VERSION));
+                }
+                // `decode` already filled the fields layout 0 lacks with empty values and
+                // tagged layout 1's Paillier keys;
+                // later layouts fill in their new fields here
+                let from = self.version;
+                self.version = STATE_VERSION;
+                MeetupEvent::StateMigrated {
+                    identity: contract_input.identity.0.clone(),
+                    from,
+                    to: STATE_VERSION,
+                }
+            }
             MeetupAction::PostRoot => {
                 // interest1, interest2, interest3, ...
-                let data = core::str::from_utf8(&contract_input.private_input).unwrap();
-                let numbers: Vec<u128> = data.split(" ").map(|x| x.parse().unwrap()).collect();
+                let numbers = parse_interests(&contract_input.private_input)?;
 
                 // create hash
                 let hash = Meetup::create_merkle_tree(&numbers);
                 self.merkle_roots.push(hash);
+                MeetupEvent::RootPosted {
+                    identity: contract_input.identity.0.clone(),
+                    root: hash,
+                    index: (self.merkle_roots.len() - 1) as u64,
+                }
+            }
+            MeetupAction::RegisterPublicKey { public_key } => {
+                let identity = contract_input.identity.0.clone();
+                if self.public_keys.contains_key(&identity) {
+                    return Err(format!("A public key is already registered for {}", identity));
+                }
+                self.public_keys.insert(identity.clone(), public_key);
+                MeetupEvent::KeyRegistered { identity }
+            }
+            MeetupAction::RotateKey { new_public_key } => {
+                let identity = contract_input.identity.0.clone();
+                match self.public_keys.get(&identity) {
+                    None => return Err(format!("No public key is registered for {}", identity)),
+                    Some(current) if *current == new_public_key => {
+                        return Err("The new public key is the registered one".to_string());
+                    }
+                    Some(_) => {}
+                }
+                // Ciphertexts under the old key must be re-encrypted
+                self.commitments.remove(&identity);
+                self.public_keys.insert(identity.clone(), new_public_key);
+                MeetupEvent::KeyRotated { identity }
+            }
+            MeetupAction::RevokeKey => {
+                let identity = contract_input.identity.0.clone();
+                if self.public_keys.remove(&identity).is_none() {
+                    return Err(format!("No public key is registered for {}", identity));
+                }
+                self.commitments.remove(&identity);
+                MeetupEvent::KeyRevoked { identity }
+            }
+            MeetupAction::AddEncryption { public_key, ciphertexts, layout } => {
+                let input: EncryptionInput = borsh::from_slice(&contract_input.private_input)
+                    .map_err(|e| format!("Could not decode the client's private input: {}", e))?;
+                let numbers = input.interests;
+
+                // Only `RegisterPublicKey` and `RotateKey` register keys, so a revoked key stays revoked
+                let identity = contract_input.identity.0.clone();
+                match self.public_keys.get(&identity) {
+                    Some(registered) if *registered == public_key => {}
+                    Some(_) => {
+                        return Err(format!("Ciphertexts are not under the public key registered for {}", identity));
+                    }
+                    None => return Err(format!("No public key is registered for {}", identity)),
+                }
+
+                if !catalog::are_valid_answers(&numbers) {
+                    return Err("Interests are not valid answers of the question catalog".to_string());
+                }
+                // Every layout has INTEREST_BITS wide slots
+                if !interest_packing().fits(&numbers) {
+                    return Err("Interest does not fit in INTEREST_BITS".to_string());
+                }
+                if ciphertexts.kind() != public_key.kind() {
+                    return Err(format!("Ciphertexts are not {} ciphertexts", public_key.kind()));
+                }
+                if !with_scheme!(public_key.kind(), S => Meetup::encrypts::<S>(&public_key, layout, &numbers, &input.nonces, &ciphertexts)) {
+                    return Err("Ciphertexts do not encrypt the private interests".to_string());
+                }
+
+                let hash = Meetup::commitment(&public_key, layout, &ciphertexts);
+                self.commitments.insert(contract_input.identity.0.clone(), hash.clone());
+                self.encrypted_message_hash = hash.clone();
+                MeetupEvent::EncryptionCommitted {
+                    identity: contract_input.identity.0.clone(),
+                    hash,
+                }
             }
-            MeetupAction::AddEncryption => {
+            MeetupAction::BlindDifferences { public_key, ciphertexts, differences } => {
+                let input: DifferencesInput = borsh::from_slice(&contract_input.private_input)
+                    .map_err(|e| format!("Could not decode the server's private input: {}", e))?;
+
+                if !self.is_committed(&public_key, &ciphertexts) {
+                    return Err("Ciphertexts are not a current commitment; the key was rotated or revoked".to_string());
+                }
+
+                if !catalog::are_valid_answers(&input.answers) {
+                    return Err("Interests are not valid answers of the question catalog".to_string());
+                }
+                if input.answers.len() != ciphertexts.len()
+                    || input.blindings.len() != ciphertexts.len()
+                    || differences.len() != ciphertexts.len()
+                {
+                    return Err("Expected one answer, blinding and difference per ciphertext".to_string());
+                }
+                if ciphertexts.kind() != public_key.kind() || differences.kind() != public_key.kind() {
+                    return Err(format!("Ciphertexts are not {} ciphertexts", public_key.kind()));
+                }
+                with_scheme!(public_key.kind(), S => Meetup::blinds::<S>(&public_key, &input, &ciphertexts, &differences))?;
+
                 let mut hasher = Sha256::new();
-                hasher.update(&contract_input.private_input);
-
-                // p, q, interest1, interest2, interest3, ...
-                let data = core::str::from_utf8(&contract_input.private_input).unwrap();
-                let numbers: Vec<u128> = data.split(" ").map(|x| x.parse().unwrap()).collect();
-
-                let p: u128 = numbers[0];
-                let q: u128 = numbers[1];
-                let (pk, sk) = Meetup::prepare_key(p, q);
-                let encrypted_messages: Vec<u128> = numbers.iter().map(|msg| Meetup::encrypt(*msg, pk)).collect();
-                let encrypted_messages_str = encrypted_messages.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(" ");
-                hasher.update(encrypted_messages_str.as_bytes());
-                let result = hasher.finalize();
-                let hash = format!("{:x}", result);
-                self.encrypted_message_hash = hash;
+                hasher.update(ciphertexts.untagged_bytes());
+                hasher.update(differences.untagged_bytes());
+                self.blinded_differences_hash = format!("{:x}", hasher.finalize());
+                MeetupEvent::DifferencesBlinded {
+                    identity: contract_input.identity.0.clone(),
+                    query_commitment: Meetup::commitment(&public_key, InterestLayout::Single, &ciphertexts),
+                    hash: self.blinded_differences_hash.clone(),
+                }
             }
-        }
+        };
 
-        // program_output might be used to give feedback to the user
-        //let program_output = format!("new value: {}", self.merkle_roots[0]);
-        Ok(("".to_string(), ctx, vec![]))
+        Ok((event.to_program_output(), ctx, vec![]))
     }
 }
 
 impl Meetup {
-    // Helper for Encryption
-    fn l_function(x: u128, n: u128) -> u128 {
-        (x - 1) / n
-    }
-
-    fn mod_inv(a: u128, m: u128) -> u128 {
-        let (g, x, _) = Self::extended_gcd(a as i128, m as i128);
-        if g == 1 {
-            ((x % m as i128 + m as i128) % m as i128) as u128
-        } else {
-            panic!("No modular inverse exists!");
+    /// Hash an `AddEncryption` commits to. Keys of different schemes have
+    /// encodings of different lengths, so the hash leaves out the scheme tags and
+    /// Paillier commitments from layout 1 states still match. Dense ones hash
+    /// their layout too, so they are never mistaken for a `BlindDifferences` query.
+    pub fn commitment(public_key: &PublicKey, layout: InterestLayout, ciphertexts: &Ciphertexts) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(public_key.untagged_bytes());
+        hasher.update(ciphertexts.untagged_bytes());
+        if layout == InterestLayout::Dense {
+            hasher.update(borsh::to_vec(&layout).expect("Failed to encode layout"));
         }
+        format!("{:x}", hasher.finalize())
     }
 
-    fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
-        if a == 0 {
-            return (b, 0, 1);
-        }
-        let (g, x1, y1) = Self::extended_gcd(b % a, a);
-        let x = y1 - (b / a) * x1;
-        let y = x1;
-        (g, x, y)
-    }
-
-    fn mod_exp(mut base: u128, mut exp: u128, modulus: u128) -> u128 {
-        let mut result = 1;
-        base = base % modulus;
-        while exp > 0 {
-            if exp % 2 == 1 {
-                result = (result * base) % modulus;
-            }
-            exp = exp >> 1;
-            base = (base * base) % modulus;
-        }
-        result
-    }
-
-    // paillier
-    fn prepare_key (p: u128, q: u128) -> ([u128; 2], [u128; 3]) {
-        let n = p * q;
-        let lambda = num_integer::lcm(p - 1, q - 1);
-        let g = n + 1; // Standard choice for g
-        let mu = Self::mod_inv(Self::l_function(Self::mod_exp(g, lambda, n * n), n), n);
-
-        ([n, g], [n, lambda, mu])
+    /// Whether `ciphertexts` are some identity's latest `AddEncryption`, in the
+    /// `Single` layout, under a key that has since been neither rotated nor revoked.
+    pub fn is_committed(&self, public_key: &PublicKey, ciphertexts: &Ciphertexts) -> bool {
+        let hash = Meetup::commitment(public_key, InterestLayout::Single, ciphertexts);
+        self.commitments
+            .iter()
+            .any(|(identity, h)| *h == hash && self.public_keys.get(identity) == Some(public_key))
     }
 
-    fn encrypt(m: u128, pk: [u128; 2]) -> u128 {
-        let [n, g] = pk;
-        let n_sq = n * n;
-        let r = 3; // Fixed r for simplicity (should be random < n)
-        (Self::mod_exp(g, m, n_sq) * Self::mod_exp(r, n, n_sq)) % n_sq
+    /// Whether `ciphertexts` are exactly the encryptions of `interests`, packed
+    /// with `layout`, under `pk` with `nonces`, which must be units modulo
+    /// `S::scalar_modulus`
+    fn encrypts<S: TaggedScheme>(
+        pk: &PublicKey,
+        layout: InterestLayout,
+        interests: &[u128],
+        nonces: &[u128],
+        ciphertexts: &Ciphertexts,
+    ) -> bool {
+        let (Some(pk), Some(ciphertexts)) = (S::key(pk), S::ciphertexts(ciphertexts)) else {
+            return false;
+        };
+        let messages = layout.packing::<S>(pk).pack_offset(interests);
+        let modulus = S::scalar_modulus(pk);
+        messages.len() == ciphertexts.len()
+            && nonces.len() == ciphertexts.len()
+            && nonces.iter().all(|r| is_unit(*r, modulus))
+            && messages
+                .iter()
+                .zip(nonces)
+                .zip(ciphertexts)
+                .all(|((m, r), c)| S::encrypt_with_nonce(pk, *m, *r) == *c)
     }
 
-    fn decrypt(c: u128, sk: [u128; 3]) -> u128 {
-        let [n, lambda, mu] = sk;
-        let n_sq = n * n;
-        let l_value = Self::l_function(Self::mod_exp(c, lambda, n_sq), n);
-        (l_value * mu) % n
+    /// Checks `differences` are `ciphertexts` minus the private answers, blinded
+    /// with the private factors (see `crypto::blinding`)
+    fn blinds<S: TaggedScheme>(
+        pk: &PublicKey,
+        input: &DifferencesInput,
+        ciphertexts: &Ciphertexts,
+        differences: &Ciphertexts,
+    ) -> Result<(), String> {
+        let (Some(pk), Some(ciphertexts), Some(differences)) =
+            (S::key(pk), S::ciphertexts(ciphertexts), S::ciphertexts(differences))
+        else {
+            return Err(format!("Ciphertexts are not {} ciphertexts", S::KIND));
+        };
+        if !input.blindings.iter().all(|b| b.is_valid::<S>(pk)) {
+            return Err("Blinding factors must be units modulo the scheme's scalar modulus".to_string());
+        }
+        let expected = blinded_differences::<S>(pk, &interest_packing(), &input.answers, ciphertexts, &input.blindings);
+        if expected != differences {
+            return Err("Differences are not the blinded differences of the private interests".to_string());
+        }
+        Ok(())
     }
 
     fn create_merkle_tree(values: &Vec<u128>) -> u128 {
@@ -163,23 +329,158 @@ impl Meetup {
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub enum MeetupAction {
     PostRoot,
-    AddEncryption,
+    /// Registers the caller's public key, of any scheme: the caller's PSI runs on that scheme.
+    RegisterPublicKey {
+        public_key: PublicKey,
+    },
+    /// Replaces the caller's registered key. Commitments under the old key are
+    /// dropped, so the caller has to `AddEncryption` again under the new one.
+    RotateKey {
+        new_public_key: PublicKey,
+    },
+    /// Removes the caller's registered key and commitment.
+    RevokeKey,
+    /// Commits to the caller's encrypted interests, laid out in plaintexts as `layout` says;
+    /// the interests and the nonces of the ciphertexts are the private input (`EncryptionInput`).
+    /// The proof of this action shows the ciphertexts encrypt valid catalog answers under the
+    /// caller's registered key. Only `Single` commitments can be answered with `BlindDifferences`.
+    AddEncryption {
+        public_key: PublicKey,
+        ciphertexts: Ciphertexts,
+        layout: InterestLayout,
+    },
+    /// The PSI server's answer to `ciphertexts`: `differences[i]` is
+    /// `Enc(x_i - y_i)` blinded and re-randomized, for the server's answers `y`.
+    /// The answers and blinding factors are the private input (`DifferencesInput`).
+    BlindDifferences {
+        public_key: PublicKey,
+        ciphertexts: Ciphertexts,
+        differences: Ciphertexts,
+    },
+    /// Upgrades a state decoded from an older layout to `STATE_VERSION` (see
+    /// `state`). Every other action refuses an outdated state.
+    Migrate,
+}
+
+/// What a successful action did, returned as the program output (see
+/// `MeetupEvent::to_program_output`) so the host and indexers don't have to
+/// diff states to find out.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event")]
+pub enum MeetupEvent {
+    /// `root` is `merkle_roots[index]`
+    RootPosted {
+        identity: String,
+        root: u128,
+        index: u64,
+    },
+    KeyRegistered {
+        identity: String,
+    },
+    KeyRotated {
+        identity: String,
+    },
+    KeyRevoked {
+        identity: String,
+    },
+    /// `hash` is the identity's new commitment
+    EncryptionCommitted {
+        identity: String,
+        hash: String,
+    },
+    /// `identity` is the answering server, `hash` the new `blinded_differences_hash`
+    DifferencesBlinded {
+        identity: String,
+        query_commitment: String,
+        hash: String,
+    },
+    StateMigrated {
+        identity: String,
+        from: u16,
+        to: u16,
+    },
+}
+
+impl MeetupEvent {
+    /// Program outputs are strings, so the borsh encoding goes out hex encoded
+    pub fn to_program_output(&self) -> String {
+        hex::encode(borsh::to_vec(self).expect("Failed to encode MeetupEvent"))
+    }
+
+    pub fn from_program_output(program_output: &[u8]) -> Result<Self, String> {
+        let bytes = hex::decode(program_output).map_err(|e| format!("Program output is not hex: {}", e))?;
+        borsh::from_slice(&bytes).map_err(|e| format!("Could not decode MeetupEvent: {}", e))
+    }
+
+    pub fn identity(&self) -> &str {
+        match self {
+            MeetupEvent::RootPosted { identity, .. }
+            | MeetupEvent::KeyRegistered { identity }
+            | MeetupEvent::KeyRotated { identity }
+            | MeetupEvent::KeyRevoked { identity }
+            | MeetupEvent::EncryptionCommitted { identity, .. }
+            | MeetupEvent::DifferencesBlinded { identity, .. }
+            | MeetupEvent::StateMigrated { identity, .. } => identity,
+        }
+    }
+}
+
+/// Private input of `MeetupAction::AddEncryption`, borsh-encoded: the interests
+/// and the random nonce of each ciphertext
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct EncryptionInput {
+    pub interests: Vec<u128>,
+    pub nonces: Vec<u128>,
+}
+
+/// Private input of `MeetupAction::BlindDifferences`, borsh-encoded
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct DifferencesInput {
+    pub answers: Vec<u128>,
+    pub blindings: Vec<Blinding>,
 }
 
-/// The state of the contract, in this example it is fully serialized on-chain
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
+/// The state of the contract, in this example it is fully serialized on-chain,
+/// in the envelope `state` describes
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Meetup {
     pub merkle_roots: Vec<u128>,
     pub encrypted_message_hash: String,
+    /// Public key of each identity, which also picks the scheme of its PSI
+    pub public_keys: BTreeMap<String, PublicKey>,
+    /// Latest `AddEncryption` commitment (see `Meetup::commitment`) of each identity
+    pub commitments: BTreeMap<String, String>,
+    /// Hash of the last proven `BlindDifferences` query and answer
+    pub blinded_differences_hash: String,
+    /// Layout the state was decoded from and is encoded in
+    pub version: u16,
 }
 
 /// Utils function for the host
 impl Meetup {
+    /// The empty state, in the current layout
+    pub fn new() -> Self {
+        Meetup {
+            merkle_roots: Vec::new(),
+            encrypted_message_hash: String::new(),
+            public_keys: BTreeMap::new(),
+            commitments: BTreeMap::new(),
+            blinded_differences_hash: String::new(),
+            version: STATE_VERSION,
+        }
+    }
+
     pub fn as_bytes(&self) -> Result<Vec<u8>, Error> {
         borsh::to_vec(self)
     }
 }
 
+impl Default for Meetup {
+    fn default() -> Self {
+        Meetup::new()
+    }
+}
+
 /// Utils function for the host
 impl MeetupAction {
     pub fn as_blob(&self, contract_name: &str) -> sdk::Blob {
@@ -195,13 +496,13 @@ impl MeetupAction {
 /// while storing the full-state off-chain
 impl Digestable for Meetup {
     fn as_digest(&self) -> sdk::StateDigest {
-        sdk::StateDigest(borsh::to_vec(self).expect("Failed to encode Balances"))
+        sdk::StateDigest(borsh::to_vec(self).expect("Failed to encode Meetup state"))
     }
 }
-impl From<sdk::StateDigest> for Meetup {
-    fn from(state: sdk::StateDigest) -> Self {
-        borsh::from_slice(&state.0)
-            .map_err(|_| "Could not decode hyllar state".to_string())
-            .unwrap()
+impl TryFrom<sdk::StateDigest> for Meetup {
+    type Error = StateError;
+
+    fn try_from(state: sdk::StateDigest) -> Result<Self, Self::Error> {
+        state::decode(&state.0)
     }
 }
@@ -0,0 +1,202 @@
+//! Which homomorphic backend the PSI runs on.
+//!
+//! Keys and ciphertexts on chain carry their scheme as an enum variant, so one
+//! contract serves Paillier and exponential ElGamal users alike. The checks
+//! themselves stay generic over `HomomorphicScheme`: [`with_scheme!`] picks the
+//! implementation from a [`SchemeKind`] and [`TaggedScheme`] unwraps the
+//! variants of that scheme.
+
+use core::fmt;
+use core::str::FromStr;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+use crypto::{Ciphertext, ElGamal, ElGamalCiphertext, ElGamalPublicKey, HomomorphicScheme, Paillier, PaillierPublicKey};
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemeKind {
+    #[default]
+    Paillier,
+    Elgamal,
+}
+
+impl fmt::Display for SchemeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SchemeKind::Paillier => "paillier",
+            SchemeKind::Elgamal => "elgamal",
+        })
+    }
+}
+
+impl FromStr for SchemeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "paillier" => Ok(SchemeKind::Paillier),
+            "elgamal" => Ok(SchemeKind::Elgamal),
+            _ => Err(format!("Unknown scheme {:?} (expected paillier or elgamal)", s)),
+        }
+    }
+}
+
+/// A public key of any scheme
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicKey {
+    Paillier(PaillierPublicKey),
+    Elgamal(ElGamalPublicKey),
+}
+
+impl PublicKey {
+    pub fn kind(&self) -> SchemeKind {
+        match self {
+            PublicKey::Paillier(_) => SchemeKind::Paillier,
+            PublicKey::Elgamal(_) => SchemeKind::Elgamal,
+        }
+    }
+
+    /// The key's own borsh encoding, which hashes of Paillier keys were computed
+    /// over before keys carried their scheme
+    pub fn untagged_bytes(&self) -> Vec<u8> {
+        match self {
+            PublicKey::Paillier(pk) => borsh::to_vec(pk),
+            PublicKey::Elgamal(pk) => borsh::to_vec(pk),
+        }
+        .expect("Failed to encode public key")
+    }
+}
+
+/// The key's own hex encoding, without the scheme
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublicKey::Paillier(pk) => fmt::Display::fmt(pk, f),
+            PublicKey::Elgamal(pk) => fmt::Display::fmt(pk, f),
+        }
+    }
+}
+
+/// Ciphertexts of one scheme
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Ciphertexts {
+    Paillier(Vec<Ciphertext>),
+    Elgamal(Vec<ElGamalCiphertext>),
+}
+
+impl Ciphertexts {
+    pub fn kind(&self) -> SchemeKind {
+        match self {
+            Ciphertexts::Paillier(_) => SchemeKind::Paillier,
+            Ciphertexts::Elgamal(_) => SchemeKind::Elgamal,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Ciphertexts::Paillier(cs) => cs.len(),
+            Ciphertexts::Elgamal(cs) => cs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The borsh encoding of the ciphertext list, without the scheme tag (see
+    /// [`PublicKey::untagged_bytes`])
+    pub fn untagged_bytes(&self) -> Vec<u8> {
+        match self {
+            Ciphertexts::Paillier(cs) => borsh::to_vec(cs),
+            Ciphertexts::Elgamal(cs) => borsh::to_vec(cs),
+        }
+        .expect("Failed to encode ciphertexts")
+    }
+}
+
+/// A scheme the contract accepts, and the variants that tag its keys and ciphertexts.
+pub trait TaggedScheme: HomomorphicScheme {
+    const KIND: SchemeKind;
+
+    /// `pk` if it is a key of this scheme
+    fn key(pk: &PublicKey) -> Option<&Self::PublicKey>;
+
+    /// `cs` if they are ciphertexts of this scheme
+    fn ciphertexts(cs: &Ciphertexts) -> Option<&[Self::Ciphertext]>;
+
+    fn tag_key(pk: Self::PublicKey) -> PublicKey;
+
+    fn tag_ciphertexts(cs: Vec<Self::Ciphertext>) -> Ciphertexts;
+}
+
+impl TaggedScheme for Paillier {
+    const KIND: SchemeKind = SchemeKind::Paillier;
+
+    fn key(pk: &PublicKey) -> Option<&Self::PublicKey> {
+        match pk {
+            PublicKey::Paillier(pk) => Some(pk),
+            _ => None,
+        }
+    }
+
+    fn ciphertexts(cs: &Ciphertexts) -> Option<&[Self::Ciphertext]> {
+        match cs {
+            Ciphertexts::Paillier(cs) => Some(cs),
+            _ => None,
+        }
+    }
+
+    fn tag_key(pk: Self::PublicKey) -> PublicKey {
+        PublicKey::Paillier(pk)
+    }
+
+    fn tag_ciphertexts(cs: Vec<Self::Ciphertext>) -> Ciphertexts {
+        Ciphertexts::Paillier(cs)
+    }
+}
+
+impl TaggedScheme for ElGamal {
+    const KIND: SchemeKind = SchemeKind::Elgamal;
+
+    fn key(pk: &PublicKey) -> Option<&Self::PublicKey> {
+        match pk {
+            PublicKey::Elgamal(pk) => Some(pk),
+            _ => None,
+        }
+    }
+
+    fn ciphertexts(cs: &Ciphertexts) -> Option<&[Self::Ciphertext]> {
+        match cs {
+            Ciphertexts::Elgamal(cs) => Some(cs),
+            _ => None,
+        }
+    }
+
+    fn tag_key(pk: Self::PublicKey) -> PublicKey {
+        PublicKey::Elgamal(pk)
+    }
+
+    fn tag_ciphertexts(cs: Vec<Self::Ciphertext>) -> Ciphertexts {
+        Ciphertexts::Elgamal(cs)
+    }
+}
+
+/// Evaluates `$body` with the type `$S` set to the [`TaggedScheme`] of `$kind`, e.g.
+/// `with_scheme!(public_key.kind(), S => S::scalar_modulus(S::key(&public_key)?))`.
+#[macro_export]
+macro_rules! with_scheme {
+    ($kind:expr, $S:ident => $body:expr) => {
+        match $kind {
+            $crate::scheme::SchemeKind::Paillier => {
+                type $S = ::crypto::Paillier;
+                $body
+            }
+            $crate::scheme::SchemeKind::Elgamal => {
+                type $S = ::crypto::ElGamal;
+                $body
+            }
+        }
+    };
+}
@@ -0,0 +1,172 @@
+//! On-chain encoding of the `Meetup` state.
+//!
+//! A state digest is an envelope: [`STATE_MAGIC`], the layout version as a
+//! little-endian `u16`, then the borsh encoding of that layout. Digests written
+//! before the envelope existed are the bare borsh encoding of the first layout,
+//! decoded as version [`LEGACY_STATE_VERSION`].
+//!
+//! A state keeps the version it was decoded from and is encoded back in it, so
+//! a proof always starts from the digest on chain. `MeetupAction::Migrate`
+//! moves it to [`STATE_VERSION`]; every other action refuses an outdated state.
+//!
+//! Adding a field means a new layout struct and version, and a conversion from
+//! the previous layout in [`decode`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use borsh::io::{Error, ErrorKind, Read, Write};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crypto::PaillierPublicKey;
+
+use crate::scheme::PublicKey;
+use crate::Meetup;
+
+/// Opens the envelope; bare legacy digests start with a roots count instead
+pub const STATE_MAGIC: [u8; 4] = *b"MTUP";
+
+/// Bare borsh encoding of `LayoutV0`, from before the envelope
+pub const LEGACY_STATE_VERSION: u16 = 0;
+
+/// Layout new states are written in
+pub const STATE_VERSION: u16 = 2;
+
+/// Why a state digest could not be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The envelope names a layout this contract doesn't know, e.g. a newer one
+    UnknownVersion(u16),
+    /// The bytes don't match the layout
+    Malformed(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::UnknownVersion(version) => {
+                write!(f, "Unknown Meetup state layout v{} (this contract knows up to v{})", version, STATE_VERSION)
+            }
+            StateError::Malformed(err) => write!(f, "Could not decode Meetup state: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// The fields of the original state: Merkle roots and the hash of the encrypted message
+#[derive(BorshSerialize, BorshDeserialize)]
+struct LayoutV0 {
+    merkle_roots: Vec<u128>,
+    encrypted_message_hash: String,
+}
+
+/// Layout 0 plus the registered keys, the encryption commitments and the blinded differences hash
+#[derive(BorshSerialize, BorshDeserialize)]
+struct LayoutV1 {
+    merkle_roots: Vec<u128>,
+    encrypted_message_hash: String,
+    public_keys: BTreeMap<String, PaillierPublicKey>,
+    commitments: BTreeMap<String, String>,
+    blinded_differences_hash: String,
+}
+
+/// Layout 1 with keys of any scheme (see `scheme::PublicKey`) instead of Paillier keys only
+#[derive(BorshSerialize, BorshDeserialize)]
+struct LayoutV2 {
+    merkle_roots: Vec<u128>,
+    encrypted_message_hash: String,
+    public_keys: BTreeMap<String, PublicKey>,
+    commitments: BTreeMap<String, String>,
+    blinded_differences_hash: String,
+}
+
+/// Decodes a state digest of any known layout.
+pub fn decode(bytes: &[u8]) -> Result<Meetup, StateError> {
+    let (version, body) = match bytes.strip_prefix(&STATE_MAGIC) {
+        Some(rest) if rest.len() >= 2 => (u16::from_le_bytes([rest[0], rest[1]]), &rest[2..]),
+        Some(_) => return Err(StateError::Malformed("Truncated envelope".to_string())),
+        None => (LEGACY_STATE_VERSION, bytes),
+    };
+    let malformed = |e: Error| StateError::Malformed(e.to_string());
+    match version {
+        LEGACY_STATE_VERSION => {
+            let layout: LayoutV0 = borsh::from_slice(body).map_err(malformed)?;
+            // Layout 0 predates these fields, `Migrate` keeps them empty
+            Ok(Meetup {
+                merkle_roots: layout.merkle_roots,
+                encrypted_message_hash: layout.encrypted_message_hash,
+                public_keys: BTreeMap::new(),
+                commitments: BTreeMap::new(),
+                blinded_differences_hash: String::new(),
+                version,
+            })
+        }
+        1 => {
+            let layout: LayoutV1 = borsh::from_slice(body).map_err(malformed)?;
+            Ok(Meetup {
+                merkle_roots: layout.merkle_roots,
+                encrypted_message_hash: layout.encrypted_message_hash,
+                public_keys: layout
+                    .public_keys
+                    .into_iter()
+                    .map(|(identity, key)| (identity, PublicKey::Paillier(key)))
+                    .collect(),
+                commitments: layout.commitments,
+                blinded_differences_hash: layout.blinded_differences_hash,
+                version,
+            })
+        }
+        2 => {
+            let layout: LayoutV2 = borsh::from_slice(body).map_err(malformed)?;
+            Ok(Meetup {
+                merkle_roots: layout.merkle_roots,
+                encrypted_message_hash: layout.encrypted_message_hash,
+                public_keys: layout.public_keys,
+                commitments: layout.commitments,
+                blinded_differences_hash: layout.blinded_differences_hash,
+                version,
+            })
+        }
+        _ => Err(StateError::UnknownVersion(version)),
+    }
+}
+
+impl BorshSerialize for Meetup {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        if self.version == LEGACY_STATE_VERSION {
+            // Only `Migrate` runs on a layout 0 state, so the newer fields are still empty
+            self.merkle_roots.serialize(writer)?;
+            return self.encrypted_message_hash.serialize(writer);
+        }
+        writer.write_all(&STATE_MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        self.merkle_roots.serialize(writer)?;
+        self.encrypted_message_hash.serialize(writer)?;
+        if self.version == 1 {
+            // Likewise a layout 1 state only holds the Paillier keys it was decoded with
+            let paillier_keys: BTreeMap<&String, &PaillierPublicKey> = self
+                .public_keys
+                .iter()
+                .filter_map(|(identity, key)| match key {
+                    PublicKey::Paillier(key) => Some((identity, key)),
+                    PublicKey::Elgamal(_) => None,
+                })
+                .collect();
+            paillier_keys.serialize(writer)?;
+        } else {
+            self.public_keys.serialize(writer)?;
+        }
+        self.commitments.serialize(writer)?;
+        self.blinded_differences_hash.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for Meetup {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        // The envelope is only ever the whole input
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        decode(&bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+}
@@ -0,0 +1,345 @@
+//! Modular arithmetic on `u128`. Moduli must stay below 2^64 so products don't overflow.
+//!
+//! [`mod_exp`] is the hot path of every encryption and decryption. It runs a
+//! fixed-window exponentiation over one of two multipliers:
+//! - inside the zkVM with the `zkvm-accel` feature, risc0's bigint accelerator,
+//!   which does a whole modular multiplication in a single ecall;
+//! - everywhere else, Montgomery multiplication, which replaces the `%` on every
+//!   step with shifts and a conditional subtraction.
+//!
+//! The `naive-modexp` feature switches back to plain square-and-multiply, which
+//! is only useful as a baseline for the cycle-count benchmark.
+
+/// Window width (in bits) for exponents long enough to amortise the table
+const WINDOW: u32 = 4;
+
+pub fn mod_inv(a: u128, m: u128) -> u128 {
+    let (g, x, _) = extended_gcd(a as i128, m as i128);
+    if g == 1 {
+        ((x % m as i128 + m as i128) % m as i128) as u128
+    } else {
+        panic!("No modular inverse exists!");
+    }
+}
+
+pub fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if a == 0 {
+        return (b, 0, 1);
+    }
+    let (g, x1, y1) = extended_gcd(b % a, a);
+    let x = y1 - (b / a) * x1;
+    let y = x1;
+    (g, x, y)
+}
+
+pub fn mod_exp(base: u128, exp: u128, modulus: u128) -> u128 {
+    if cfg!(feature = "naive-modexp") {
+        mod_exp_naive(base, exp, modulus)
+    } else {
+        mod_exp_fast(base, exp, modulus)
+    }
+}
+
+/// Plain square-and-multiply
+pub fn mod_exp_naive(mut base: u128, mut exp: u128, modulus: u128) -> u128 {
+    let mut result = 1;
+    base %= modulus;
+    while exp > 0 {
+        if exp % 2 == 1 {
+            result = (result * base) % modulus;
+        }
+        exp >>= 1;
+        base = (base * base) % modulus;
+    }
+    result
+}
+
+/// Deterministic Miller-Rabin, exact for every `n` below 2^64.
+pub fn is_prime(n: u128) -> bool {
+    const WITNESSES: [u128; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    for w in WITNESSES {
+        if n.is_multiple_of(w) {
+            return n == w;
+        }
+    }
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    'witness: for a in WITNESSES {
+        let mut x = mod_exp(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = (x * x) % n;
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+#[cfg(all(feature = "zkvm-accel", target_os = "zkvm"))]
+fn mod_exp_fast(base: u128, exp: u128, modulus: u128) -> u128 {
+    window_exp(&accel::BigIntMul::new(modulus), base, exp)
+}
+
+#[cfg(not(all(feature = "zkvm-accel", target_os = "zkvm")))]
+fn mod_exp_fast(base: u128, exp: u128, modulus: u128) -> u128 {
+    if Montgomery::supports(modulus) {
+        window_exp(&Montgomery::new(modulus), base, exp)
+    } else {
+        mod_exp_naive(base, exp, modulus)
+    }
+}
+
+/// A modular multiplier working on its own representation of residues.
+trait ModMul {
+    /// The representation of 1
+    fn one(&self) -> u128;
+    /// Converts a plain integer into the multiplier's representation
+    fn enter(&self, x: u128) -> u128;
+    /// Converts back to a plain residue
+    fn leave(&self, x: u128) -> u128;
+    fn mul(&self, a: u128, b: u128) -> u128;
+}
+
+/// Left-to-right fixed-window exponentiation. Short exponents (like the
+/// plaintexts in `g^m`) use a one-bit window so they don't pay for a table.
+fn window_exp<M: ModMul>(m: &M, base: u128, exp: u128) -> u128 {
+    let bits = 128 - exp.leading_zeros();
+    let window = if bits <= 2 * WINDOW { 1 } else { WINDOW };
+
+    let mut table = [m.one(); 1 << WINDOW];
+    table[1] = m.enter(base);
+    for i in 2..(1 << window) {
+        table[i] = m.mul(table[i - 1], table[1]);
+    }
+
+    let mut result = m.one();
+    let mask = (1 << window) - 1;
+    for w in (0..bits.div_ceil(window)).rev() {
+        for _ in 0..window {
+            result = m.mul(result, result);
+        }
+        let digit = ((exp >> (w * window)) & mask) as usize;
+        if digit != 0 {
+            result = m.mul(result, table[digit]);
+        }
+    }
+    m.leave(result)
+}
+
+/// Montgomery multiplication with `R = 2^64`, for odd moduli below 2^63 (so
+/// that `T + u * m` in the reduction can't overflow a u128).
+struct Montgomery {
+    m: u128,
+    /// `-m^-1 mod 2^64`
+    m_prime: u64,
+    /// `R mod m`
+    r: u128,
+    /// `R^2 mod m`
+    r2: u128,
+}
+
+impl Montgomery {
+    fn supports(modulus: u128) -> bool {
+        modulus > 1 && modulus % 2 == 1 && modulus < 1 << 63
+    }
+
+    fn new(m: u128) -> Self {
+        // Newton iteration: each step doubles the number of correct low bits
+        let m64 = m as u64;
+        let mut inv: u64 = 1;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m64.wrapping_mul(inv)));
+        }
+        let r = (1u128 << 64) % m;
+
+        Montgomery { m, m_prime: inv.wrapping_neg(), r, r2: (r * r) % m }
+    }
+
+    /// `t * R^-1 mod m`, for `t < m * R`
+    fn redc(&self, t: u128) -> u128 {
+        let u = (t as u64).wrapping_mul(self.m_prime) as u128;
+        let t = (t + u * self.m) >> 64;
+        if t >= self.m {
+            t - self.m
+        } else {
+            t
+        }
+    }
+}
+
+impl ModMul for Montgomery {
+    fn one(&self) -> u128 {
+        self.r
+    }
+
+    fn enter(&self, x: u128) -> u128 {
+        self.redc((x % self.m) * self.r2)
+    }
+
+    fn leave(&self, x: u128) -> u128 {
+        self.redc(x)
+    }
+
+    fn mul(&self, a: u128, b: u128) -> u128 {
+        self.redc(a * b)
+    }
+}
+
+#[cfg(all(feature = "zkvm-accel", target_os = "zkvm"))]
+mod accel {
+    use risc0_zkvm_platform::syscall::{bigint, sys_bigint};
+
+    type Words = [u32; bigint::WIDTH_WORDS];
+
+    /// Modular multiplication through the zkVM's 256-bit bigint circuit.
+    pub(super) struct BigIntMul {
+        m: u128,
+        modulus: Words,
+    }
+
+    impl BigIntMul {
+        pub(super) fn new(m: u128) -> Self {
+            BigIntMul { m, modulus: to_words(m) }
+        }
+    }
+
+    fn to_words(x: u128) -> Words {
+        let mut words = [0u32; bigint::WIDTH_WORDS];
+        for (i, word) in words.iter_mut().take(4).enumerate() {
+            *word = (x >> (32 * i)) as u32;
+        }
+        words
+    }
+
+    fn from_words(words: &Words) -> u128 {
+        words[..4]
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, word)| acc | (*word as u128) << (32 * i))
+    }
+
+    impl super::ModMul for BigIntMul {
+        fn one(&self) -> u128 {
+            1
+        }
+
+        fn enter(&self, x: u128) -> u128 {
+            x % self.m
+        }
+
+        fn leave(&self, x: u128) -> u128 {
+            x
+        }
+
+        fn mul(&self, a: u128, b: u128) -> u128 {
+            let mut result = [0u32; bigint::WIDTH_WORDS];
+            // SAFETY: all four pointers reference live, correctly sized arrays.
+            unsafe {
+                sys_bigint(&mut result, bigint::OP_MULTIPLY, &to_words(a), &to_words(b), &self.modulus);
+            }
+            from_words(&result)
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// xorshift64*, enough to spread test inputs around
+    pub(crate) struct TestRng(pub u64);
+
+    impl TestRng {
+        pub(crate) fn next(&mut self) -> u128 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) as u128
+        }
+
+        pub(crate) fn below(&mut self, bound: u128) -> u128 {
+            self.next() % bound
+        }
+    }
+
+    /// Residues as they are, like the zkVM accelerator's multiplier
+    struct PlainMul(u128);
+
+    impl ModMul for PlainMul {
+        fn one(&self) -> u128 {
+            1
+        }
+
+        fn enter(&self, x: u128) -> u128 {
+            x % self.0
+        }
+
+        fn leave(&self, x: u128) -> u128 {
+            x
+        }
+
+        fn mul(&self, a: u128, b: u128) -> u128 {
+            (a * b) % self.0
+        }
+    }
+
+    #[test]
+    fn montgomery_window_exp_matches_naive() {
+        let mut rng = TestRng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            // Odd moduli of every size Montgomery supports
+            let bits = 2 + rng.below(62) as u32;
+            let modulus = (rng.below(1 << bits) | 1).max(3);
+            let base = rng.next();
+            let exp_bits = rng.below(64);
+            let exp = rng.below(1 << exp_bits);
+            let expected = mod_exp_naive(base, exp, modulus);
+            assert_eq!(window_exp(&Montgomery::new(modulus), base, exp), expected, "{}^{} mod {}", base, exp, modulus);
+            assert_eq!(mod_exp(base, exp, modulus), expected);
+        }
+    }
+
+    #[test]
+    fn montgomery_handles_edge_inputs() {
+        for modulus in [3, 5, (1 << 62) + 1, (1 << 63) - 25] {
+            let m = Montgomery::new(modulus);
+            assert_eq!(window_exp(&m, 0, 0), 1);
+            assert_eq!(window_exp(&m, 0, 5), 0);
+            assert_eq!(window_exp(&m, modulus - 1, 2), 1);
+            assert_eq!(window_exp(&m, modulus + 2, 3), mod_exp_naive(2, 3, modulus));
+        }
+        // Even and oversized moduli fall back to square-and-multiply
+        assert_eq!(mod_exp(3, 100, 1 << 40), mod_exp_naive(3, 100, 1 << 40));
+        assert_eq!(mod_exp(3, 100, (1 << 63) + 1), mod_exp_naive(3, 100, (1 << 63) + 1));
+    }
+
+    /// `window_exp` over the residue representation the `zkvm-accel` multiplier uses
+    #[test]
+    fn window_exp_over_plain_residues_matches_naive() {
+        let mut rng = TestRng(0x0123_4567_89ab_cdef);
+        for _ in 0..2000 {
+            let modulus = 2 + rng.below(1 << 63);
+            let base = rng.next();
+            let exp = rng.next();
+            assert_eq!(window_exp(&PlainMul(modulus), base, exp), mod_exp_naive(base, exp, modulus));
+        }
+    }
+
+    #[test]
+    fn is_prime_matches_trial_division() {
+        let trial = |n: u128| n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d));
+        for n in 0..5000 {
+            assert_eq!(is_prime(n), trial(n), "{}", n);
+        }
+        assert!(is_prime((1 << 61) - 1));
+        assert!(!is_prime(3_215_031_751)); // Strong pseudoprime to bases 2, 3, 5 and 7
+    }
+}
@@ -0,0 +1,100 @@
+//! Blinding the PSI differences before they go back to the querying party.
+//!
+//! A plain `Enc(x - y)` leaks `x - y` on decryption, and with it the other
+//! party's answer. Each difference is instead multiplied by a random scalar
+//! that is a unit modulo the plaintext modulus, so it still decrypts to zero
+//! exactly when `x == y` and to an unrelated value otherwise, then
+//! re-randomized so it can't be matched against the query ciphertexts.
+//!
+//! Blinding is per difference, so the query has to use [`Packing::single`]:
+//! scaling a packed plaintext would mix its slots together.
+
+use alloc::vec::Vec;
+
+use crate::arith::extended_gcd;
+use crate::encoding::{versioned_encoding, ENCODING_VERSION};
+use crate::packing::Packing;
+use crate::scheme::HomomorphicScheme;
+
+/// The random factors hiding one difference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blinding {
+    pub scalar: u128,
+    pub nonce: u128,
+}
+
+versioned_encoding!(Blinding, ENCODING_VERSION, { scalar, nonce });
+
+impl Blinding {
+    /// Both factors must be units modulo `S::scalar_modulus`, otherwise a
+    /// non-match could be blinded into a zero.
+    pub fn is_valid<S: HomomorphicScheme>(&self, pk: &S::PublicKey) -> bool {
+        let modulus = S::scalar_modulus(pk);
+        is_unit(self.scalar, modulus) && is_unit(self.nonce, modulus)
+    }
+}
+
+/// Whether `x` lies in `[1, modulus)` and is invertible modulo `modulus`.
+pub fn is_unit(x: u128, modulus: u128) -> bool {
+    x != 0 && x < modulus && extended_gcd(x as i128, modulus as i128).0 == 1
+}
+
+/// Server side of the PSI: for each query ciphertext `Enc(x_i + B)` and own
+/// answer `y_i`, `blind(Enc(x_i + B) - Enc(y_i + B))`.
+pub fn blinded_differences<S: HomomorphicScheme>(
+    pk: &S::PublicKey,
+    packing: &Packing,
+    answers: &[u128],
+    queries: &[S::Ciphertext],
+    blindings: &[Blinding],
+) -> Vec<S::Ciphertext> {
+    assert!(packing.slots == 1, "Blinded differences need one value per plaintext!");
+    assert!(
+        answers.len() == queries.len() && answers.len() == blindings.len(),
+        "Answers, queries and blindings must line up!"
+    );
+    queries
+        .iter()
+        .zip(packing.pack_offset(answers))
+        .zip(blindings)
+        .map(|((c, y), b)| S::blind(pk, &S::sub(pk, c, &S::encrypt(pk, y)), b.scalar, b.nonce))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paillier::{encrypt_with_nonce, prepare_key};
+    use crate::Paillier;
+
+    #[test]
+    fn only_matching_answers_blind_to_zero() {
+        let (pk, sk) = prepare_key(18443, 17041);
+        let packing = Packing::single(6);
+        let queries: Vec<_> = packing
+            .pack_offset(&[1, 9, 12, 18])
+            .into_iter()
+            .zip([5, 7, 11, 13])
+            .map(|(m, r)| encrypt_with_nonce(m, r, &pk))
+            .collect();
+        let blindings = [
+            Blinding { scalar: 101, nonce: 103 },
+            Blinding { scalar: 107, nonce: 109 },
+            Blinding { scalar: 113, nonce: 127 },
+            Blinding { scalar: 131, nonce: 137 },
+        ];
+        let differences = blinded_differences::<Paillier>(&pk, &packing, &[1, 8, 12, 19], &queries, &blindings);
+        assert_eq!(Paillier::is_zero_batch(&sk, &differences), [true, false, true, false]);
+        // The difference of 1 comes back scaled by the blinding
+        assert_eq!(Paillier::decrypt(&sk, &differences[1]), 107);
+    }
+
+    #[test]
+    fn blindings_must_be_units() {
+        let (pk, _) = prepare_key(18443, 17041);
+        assert!(Blinding { scalar: 2, nonce: 3 }.is_valid::<Paillier>(&pk));
+        assert!(!Blinding { scalar: 0, nonce: 3 }.is_valid::<Paillier>(&pk));
+        assert!(!Blinding { scalar: 18443, nonce: 3 }.is_valid::<Paillier>(&pk));
+        assert!(!Blinding { scalar: 2, nonce: pk.n }.is_valid::<Paillier>(&pk));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::arith::{mod_exp, mod_inv};
+use crate::encoding::{hex_encoding, read_version, versioned_encoding, ENCODING_VERSION};
+use crate::scheme::HomomorphicScheme;
+
+/// Exponential ElGamal over `Z_p^*`: messages are encoded as `g^m`, which makes
+/// the scheme additively homomorphic at the cost of a discrete log on decryption.
+pub struct ElGamal;
+
+/// Plaintexts are recovered by brute force, so they must stay below this bound.
+pub const ELGAMAL_DLOG_BOUND: u128 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElGamalParams {
+    /// Prime modulus, below 2^64 so products fit in a u128
+    pub p: u128,
+    pub g: u128,
+    /// Secret exponent
+    pub x: u128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElGamalPublicKey {
+    pub p: u128,
+    pub g: u128,
+    /// `g^x mod p`
+    pub h: u128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElGamalSecretKey {
+    pub p: u128,
+    pub g: u128,
+    pub x: u128,
+}
+
+/// `(g^r, g^m * h^r)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElGamalCiphertext(pub u128, pub u128);
+
+versioned_encoding!(ElGamalPublicKey, ENCODING_VERSION, { p, g, h });
+versioned_encoding!(ElGamalCiphertext, ENCODING_VERSION, { 0, 1 });
+
+impl borsh::BorshSerialize for ElGamalSecretKey {
+    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        borsh::BorshSerialize::serialize(&ENCODING_VERSION, writer)?;
+        borsh::BorshSerialize::serialize(&self.p, writer)?;
+        borsh::BorshSerialize::serialize(&self.g, writer)?;
+        borsh::BorshSerialize::serialize(&self.x, writer)
+    }
+}
+
+impl borsh::BorshDeserialize for ElGamalSecretKey {
+    fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        read_version(reader, ENCODING_VERSION, "ElGamalSecretKey")?;
+        let p = <u128 as borsh::BorshDeserialize>::deserialize_reader(reader)?;
+        let g = <u128 as borsh::BorshDeserialize>::deserialize_reader(reader)?;
+        let x = <u128 as borsh::BorshDeserialize>::deserialize_reader(reader)?;
+        // Products modulo `p` have to fit in a u128
+        if !(3..1 << 64).contains(&p) || !(2..p).contains(&g) {
+            return Err(borsh::io::Error::new(
+                borsh::io::ErrorKind::InvalidData,
+                "ElGamalSecretKey group is not a valid one",
+            ));
+        }
+        Ok(ElGamalSecretKey { p, g, x })
+    }
+}
+
+hex_encoding!(ElGamalSecretKey);
+
+impl ElGamalSecretKey {
+    pub fn public_key(&self) -> ElGamalPublicKey {
+        ElGamalPublicKey {
+            p: self.p,
+            g: self.g,
+            h: mod_exp(self.g, self.x, self.p),
+        }
+    }
+}
+
+impl ElGamal {
+    /// Returns `g^m mod p` for the ciphertext.
+    fn unmask(sk: &ElGamalSecretKey, c: &ElGamalCiphertext) -> u128 {
+        let s = mod_exp(c.0, sk.x, sk.p);
+        (c.1 * mod_inv(s, sk.p)) % sk.p
+    }
+}
+
+impl HomomorphicScheme for ElGamal {
+    type KeyParams = ElGamalParams;
+    type PublicKey = ElGamalPublicKey;
+    type SecretKey = ElGamalSecretKey;
+    type Ciphertext = ElGamalCiphertext;
+
+    fn keygen(params: &Self::KeyParams) -> (Self::PublicKey, Self::SecretKey) {
+        let ElGamalParams { p, g, x } = *params;
+        let h = mod_exp(g, x, p);
+
+        (ElGamalPublicKey { p, g, h }, ElGamalSecretKey { p, g, x })
+    }
+
+    /// Limited by the discrete log search rather than by `p`.
+    fn plaintext_bits(_pk: &Self::PublicKey) -> u32 {
+        ELGAMAL_DLOG_BOUND.trailing_zeros()
+    }
+
+    fn scalar_modulus(pk: &Self::PublicKey) -> u128 {
+        pk.p - 1
+    }
+
+    fn encrypt(pk: &Self::PublicKey, m: u128) -> Self::Ciphertext {
+        Self::encrypt_with_nonce(pk, m, 3)
+    }
+
+    fn encrypt_with_nonce(pk: &Self::PublicKey, m: u128, r: u128) -> Self::Ciphertext {
+        let c1 = mod_exp(pk.g, r, pk.p);
+        let c2 = (mod_exp(pk.g, m, pk.p) * mod_exp(pk.h, r, pk.p)) % pk.p;
+        ElGamalCiphertext(c1, c2)
+    }
+
+    /// Negative plaintexts come back as `(p - 1) - |m|`, the same wrap-around as the group order.
+    fn decrypt(sk: &Self::SecretKey, c: &Self::Ciphertext) -> u128 {
+        let g_m = Self::unmask(sk, c);
+        let g_inv = mod_inv(sk.g, sk.p);
+
+        let mut pos = 1;
+        let mut neg = 1;
+        for m in 0..ELGAMAL_DLOG_BOUND {
+            if pos == g_m {
+                return m;
+            }
+            if neg == g_m {
+                return (sk.p - 1) - m;
+            }
+            pos = (pos * sk.g) % sk.p;
+            neg = (neg * g_inv) % sk.p;
+        }
+        panic!("Plaintext is outside the discrete log bound!");
+    }
+
+    fn add(pk: &Self::PublicKey, a: &Self::Ciphertext, b: &Self::Ciphertext) -> Self::Ciphertext {
+        ElGamalCiphertext((a.0 * b.0) % pk.p, (a.1 * b.1) % pk.p)
+    }
+
+    fn scalar_mul(pk: &Self::PublicKey, c: &Self::Ciphertext, k: u128) -> Self::Ciphertext {
+        ElGamalCiphertext(mod_exp(c.0, k, pk.p), mod_exp(c.1, k, pk.p))
+    }
+
+    fn negate(pk: &Self::PublicKey, c: &Self::Ciphertext) -> Self::Ciphertext {
+        ElGamalCiphertext(mod_inv(c.0, pk.p), mod_inv(c.1, pk.p))
+    }
+
+    /// `(c1 * g^r, c2 * h^r)`
+    fn rerandomize(pk: &Self::PublicKey, c: &Self::Ciphertext, r: u128) -> Self::Ciphertext {
+        ElGamalCiphertext(
+            (c.0 * mod_exp(pk.g, r, pk.p)) % pk.p,
+            (c.1 * mod_exp(pk.h, r, pk.p)) % pk.p,
+        )
+    }
+
+    fn is_zero(sk: &Self::SecretKey, c: &Self::Ciphertext) -> bool {
+        Self::unmask(sk, c) == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// p = 2^61 - 1
+    const PARAMS: ElGamalParams = ElGamalParams {
+        p: 2305843009213693951,
+        g: 3,
+        x: 1234567,
+    };
+
+    #[test]
+    fn keys_and_ciphertexts_round_trip_through_hex() {
+        let (pk, sk) = ElGamal::keygen(&PARAMS);
+        assert_eq!(sk.public_key(), pk);
+        let c = ElGamal::encrypt_with_nonce(&pk, 9, 1001);
+        assert_eq!(ElGamalPublicKey::from_hex(&pk.to_hex()).unwrap(), pk);
+        assert_eq!(ElGamalSecretKey::from_hex(&sk.to_hex()).unwrap(), sk);
+        assert_eq!(ElGamalCiphertext::from_hex(&c.to_hex()).unwrap(), c);
+        let bad_group = ElGamalSecretKey { p: 1 << 64, ..sk };
+        assert!(ElGamalSecretKey::from_hex(&bad_group.to_hex()).is_err());
+    }
+
+    #[test]
+    fn negative_differences_wrap_around_the_group_order() {
+        let (pk, sk) = ElGamal::keygen(&PARAMS);
+        let (a, b) = (ElGamal::encrypt(&pk, 5), ElGamal::encrypt_with_nonce(&pk, 12, 77));
+        assert_eq!(ElGamal::decrypt(&sk, &ElGamal::sub(&pk, &b, &a)), 7);
+        assert_eq!(ElGamal::decrypt(&sk, &ElGamal::sub(&pk, &a, &b)), (PARAMS.p - 1) - 7);
+        assert_eq!(ElGamal::decrypt(&sk, &ElGamal::negate(&pk, &a)), (PARAMS.p - 1) - 5);
+        assert!(ElGamal::is_zero(&sk, &ElGamal::sub(&pk, &a, &ElGamal::encrypt(&pk, 5))));
+    }
+
+    #[test]
+    fn blinding_keeps_only_whether_the_difference_is_zero() {
+        let (pk, sk) = ElGamal::keygen(&PARAMS);
+        let query = ElGamal::encrypt_with_nonce(&pk, 9, 1001);
+        let (scalar, nonce) = (1_000_003, 424_243);
+
+        let same = ElGamal::blind(&pk, &ElGamal::sub(&pk, &query, &ElGamal::encrypt(&pk, 9)), scalar, nonce);
+        assert!(ElGamal::is_zero(&sk, &same));
+        assert_ne!(same, ElGamal::sub(&pk, &query, &ElGamal::encrypt(&pk, 9)));
+
+        let other = ElGamal::blind(&pk, &ElGamal::sub(&pk, &query, &ElGamal::encrypt(&pk, 8)), scalar, nonce);
+        assert!(!ElGamal::is_zero(&sk, &other));
+        // `g^(k * 1)` is far outside the discrete log bound, so the difference itself is hidden
+        let g_m = ElGamal::unmask(&sk, &other);
+        assert_eq!(g_m, mod_exp(PARAMS.g, scalar, PARAMS.p));
+    }
+}
@@ -0,0 +1,115 @@
+//! Wire encodings for keys and ciphertexts.
+//!
+//! The borsh encoding is prefixed with a version byte so the layout can change
+//! without old blobs being silently misread. The JSON encoding is that same
+//! versioned borsh encoding as a hex string, which also serves the CLI.
+
+use alloc::string::String;
+use core::fmt;
+
+/// Version byte written in front of encoded public keys and ciphertexts
+pub const ENCODING_VERSION: u8 = 1;
+
+/// Version 2 stores the primes instead of `[n, lambda, mu]`
+pub const SECRET_KEY_ENCODING_VERSION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Hex(hex::FromHexError),
+    Borsh(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Hex(e) => write!(f, "invalid hex: {}", e),
+            DecodeError::Borsh(e) => write!(f, "invalid encoding: {}", e),
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+/// Reads the version byte and rejects anything but `expected`.
+pub(crate) fn read_version<R: borsh::io::Read>(reader: &mut R, expected: u8, name: &str) -> borsh::io::Result<()> {
+    let version = <u8 as borsh::BorshDeserialize>::deserialize_reader(reader)?;
+    if version != expected {
+        return Err(borsh::io::Error::new(
+            borsh::io::ErrorKind::InvalidData,
+            alloc::format!("unsupported {} encoding version {}", name, version),
+        ));
+    }
+    Ok(())
+}
+
+/// Implements the versioned borsh encoding for a struct whose fields are all
+/// `u128`, along with everything `hex_encoding!` provides.
+macro_rules! versioned_encoding {
+    ($ty:ident, $version:expr, { $($field:tt),+ $(,)? }) => {
+        impl borsh::BorshSerialize for $ty {
+            fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+                borsh::BorshSerialize::serialize(&$version, writer)?;
+                $(borsh::BorshSerialize::serialize(&self.$field, writer)?;)+
+                Ok(())
+            }
+        }
+
+        impl borsh::BorshDeserialize for $ty {
+            fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+                $crate::encoding::read_version(reader, $version, stringify!($ty))?;
+                Ok(Self {
+                    $($field: <u128 as borsh::BorshDeserialize>::deserialize_reader(reader)?,)+
+                })
+            }
+        }
+
+        $crate::encoding::hex_encoding!($ty);
+    };
+}
+
+/// Implements the hex/JSON, `Display` and `FromStr` encodings on top of the
+/// type's borsh encoding.
+macro_rules! hex_encoding {
+    ($ty:ident) => {
+        impl $ty {
+            pub fn to_hex(&self) -> alloc::string::String {
+                hex::encode(borsh::to_vec(self).expect("Failed to encode"))
+            }
+
+            pub fn from_hex(s: &str) -> Result<Self, $crate::encoding::DecodeError> {
+                let bytes = hex::decode(s.trim_start_matches("0x")).map_err($crate::encoding::DecodeError::Hex)?;
+                borsh::from_slice(&bytes).map_err(|e| $crate::encoding::DecodeError::Borsh(alloc::string::ToString::to_string(&e)))
+            }
+        }
+
+        impl core::fmt::Display for $ty {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.write_str(&self.to_hex())
+            }
+        }
+
+        impl core::str::FromStr for $ty {
+            type Err = $crate::encoding::DecodeError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Self::from_hex(s)
+            }
+        }
+
+        impl serde::Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_hex())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = <alloc::string::String as serde::Deserialize>::deserialize(deserializer)?;
+                Self::from_hex(&s).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+pub(crate) use hex_encoding;
+pub(crate) use versioned_encoding;
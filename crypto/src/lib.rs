@@ -0,0 +1,21 @@
+//! Homomorphic encryption shared by the contract (and therefore the guest) and the host.
+//!
+//! Everything here is `no_std` so it can run unchanged inside the RISC Zero zkVM.
+#![no_std]
+
+extern crate alloc;
+
+pub mod arith;
+pub mod blinding;
+pub mod elgamal;
+pub mod encoding;
+pub mod packing;
+pub mod paillier;
+pub mod scheme;
+
+pub use blinding::Blinding;
+pub use elgamal::{ElGamal, ElGamalCiphertext, ElGamalParams, ElGamalPublicKey, ElGamalSecretKey};
+pub use encoding::DecodeError;
+pub use packing::Packing;
+pub use paillier::{Ciphertext, Paillier, PaillierPublicKey, PaillierSecretKey};
+pub use scheme::HomomorphicScheme;
@@ -0,0 +1,215 @@
+//! Packing several small values into one plaintext.
+//!
+//! Interests are only a few bits wide while a plaintext holds `plaintext_bits`
+//! of them, so values are laid out in fixed-width slots, lowest slot first.
+//! The PSI subtracts Bob's packed values from Alice's packed ciphertext, so the
+//! layout has to keep slot differences from borrowing across slots:
+//! - Alice's values are packed with an offset `B = 2^value_bits` in each slot
+//!   (see [`Packing::pack_offset`]);
+//! - Bob's values are packed as-is ([`Packing::pack`]).
+//!
+//! Each slot of the difference is then `B + x - y`, which lies in `(0, 2B)` and
+//! fits the `value_bits + 1` slot width. It equals `B` exactly when `x == y`.
+//!
+//! Blinded differences need one value per plaintext (see `blinding`): scaling a
+//! packed plaintext would mix its slots. Blinded queries therefore use
+//! [`Packing::single`], and the dense layouts ([`Packing::for_key`]) only suit
+//! differences nobody blinds.
+
+use alloc::vec::Vec;
+
+use crate::scheme::HomomorphicScheme;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packing {
+    /// Width of the values being packed
+    pub value_bits: u32,
+    /// Number of slots in one plaintext
+    pub slots: usize,
+}
+
+impl Packing {
+    pub fn new(value_bits: u32, plaintext_bits: u32) -> Self {
+        let slots = (plaintext_bits / (value_bits + 1)) as usize;
+        assert!(slots > 0, "Plaintext space is too small to hold a single slot!");
+        Packing { value_bits, slots }
+    }
+
+    /// One value per plaintext. Needed whenever each value's difference is
+    /// blinded on its own (see `blinding`).
+    pub fn single(value_bits: u32) -> Self {
+        Packing { value_bits, slots: 1 }
+    }
+
+    /// The densest layout for values of `value_bits` bits under `pk`.
+    pub fn for_key<S: HomomorphicScheme>(pk: &S::PublicKey, value_bits: u32) -> Self {
+        Self::new(value_bits, S::plaintext_bits(pk))
+    }
+
+    fn slot_bits(&self) -> u32 {
+        self.value_bits + 1
+    }
+
+    fn offset(&self) -> u128 {
+        1 << self.value_bits
+    }
+
+    /// How many plaintexts `count` values take.
+    pub fn plaintexts_for(&self, count: usize) -> usize {
+        count.div_ceil(self.slots)
+    }
+
+    /// Whether every value fits in `value_bits`.
+    pub fn fits(&self, values: &[u128]) -> bool {
+        values.iter().all(|v| *v < self.offset())
+    }
+
+    /// Packs `values`, zero-padding the last plaintext.
+    pub fn pack(&self, values: &[u128]) -> Vec<u128> {
+        self.pack_with(values, 0)
+    }
+
+    /// Packs `values + B`; the side the other party's values get subtracted from.
+    pub fn pack_offset(&self, values: &[u128]) -> Vec<u128> {
+        self.pack_with(values, self.offset())
+    }
+
+    fn pack_with(&self, values: &[u128], offset: u128) -> Vec<u128> {
+        assert!(self.fits(values), "Value does not fit in its slot!");
+        values
+            .chunks(self.slots)
+            .map(|chunk| {
+                let mut padded = chunk.to_vec();
+                padded.resize(self.slots, 0);
+                padded
+                    .iter()
+                    .rev()
+                    .fold(0, |acc, v| (acc << self.slot_bits()) | (v + offset))
+            })
+            .collect()
+    }
+
+    /// Splits plaintexts back into their first `count` slot values.
+    pub fn unpack(&self, plaintexts: &[u128], count: usize) -> Vec<u128> {
+        let mask = (1 << self.slot_bits()) - 1;
+        plaintexts
+            .iter()
+            .flat_map(|p| (0..self.slots).map(move |i| (p >> (i as u32 * self.slot_bits())) & mask))
+            .take(count)
+            .collect()
+    }
+
+    /// For decrypted `pack_offset(x) - pack(y)` plaintexts, whether each `x_i == y_i`.
+    pub fn matches(&self, plaintexts: &[u128], count: usize) -> Vec<bool> {
+        self.unpack(plaintexts, count)
+            .into_iter()
+            .map(|slot| slot == self.offset())
+            .collect()
+    }
+
+    /// Encrypts `values` packed with the offset, as the querying party does,
+    /// with one fresh nonce per plaintext.
+    pub fn encrypt<S: HomomorphicScheme>(&self, pk: &S::PublicKey, values: &[u128], nonces: &[u128]) -> Vec<S::Ciphertext> {
+        let plaintexts = self.pack_offset(values);
+        assert!(plaintexts.len() == nonces.len(), "Expected one nonce per plaintext!");
+        plaintexts
+            .iter()
+            .zip(nonces)
+            .map(|(p, r)| S::encrypt_with_nonce(pk, *p, *r))
+            .collect()
+    }
+
+    /// `Enc(pack_offset(x)) - Enc(pack(y))` for each query plaintext, unblinded:
+    /// only for differences the key holder may learn, since they reveal `x - y`.
+    pub fn differences<S: HomomorphicScheme>(&self, pk: &S::PublicKey, queries: &[S::Ciphertext], answers: &[u128]) -> Vec<S::Ciphertext> {
+        let packed = self.pack(answers);
+        assert!(packed.len() == queries.len(), "Expected one query per packed plaintext!");
+        queries
+            .iter()
+            .zip(packed)
+            .map(|(c, y)| S::sub(pk, c, &S::encrypt(pk, y)))
+            .collect()
+    }
+
+    /// Decrypts packed differences and tells, for each of the `count` values, whether it matched.
+    pub fn decrypt_matches<S: HomomorphicScheme>(&self, sk: &S::SecretKey, cs: &[S::Ciphertext], count: usize) -> Vec<bool> {
+        self.matches(&S::decrypt_batch(sk, cs), count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paillier::prepare_key;
+    use crate::Paillier;
+
+    /// Three 6-bit values per plaintext
+    const DENSE: Packing = Packing { value_bits: 6, slots: 3 };
+
+    #[test]
+    fn slot_differences_do_not_borrow() {
+        let x = [0, 63, 17, 5, 63, 0, 40];
+        let y = [63, 0, 17, 4, 63, 1, 40];
+        let diffs: Vec<u128> = DENSE
+            .pack_offset(&x)
+            .iter()
+            .zip(DENSE.pack(&y))
+            .map(|(a, b)| a - b)
+            .collect();
+        let slots = DENSE.unpack(&diffs, x.len());
+        for ((slot, x), y) in slots.iter().zip(x).zip(y) {
+            assert_eq!(*slot, 64 + x - y);
+        }
+        assert_eq!(
+            DENSE.matches(&diffs, x.len()),
+            [false, false, true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn unpack_undoes_pack_offset() {
+        let values: Vec<u128> = (0..64).collect();
+        let unpacked = DENSE.unpack(&DENSE.pack_offset(&values), values.len());
+        let offset_back: Vec<u128> = unpacked.iter().map(|v| v - 64).collect();
+        assert_eq!(offset_back, values);
+        assert_eq!(DENSE.unpack(&DENSE.pack(&values), values.len()), values);
+    }
+
+    #[test]
+    fn last_plaintext_is_padded() {
+        let values = [1, 2, 3, 4];
+        assert_eq!(DENSE.plaintexts_for(values.len()), 2);
+        let packed = DENSE.pack(&values);
+        assert_eq!(packed.len(), 2);
+        // Only the first slot of the last plaintext is used
+        assert_eq!(packed[1], 4);
+        // The padding slots carry the offset like any other
+        assert_eq!(DENSE.pack_offset(&values)[1], (64 << 14) | (64 << 7) | (4 + 64));
+        assert_eq!(DENSE.unpack(&packed, 6), [1, 2, 3, 4, 0, 0]);
+    }
+
+    #[test]
+    fn dense_packing_matches_through_encryption() {
+        let (pk, sk) = prepare_key(18443, 17041);
+        let packing = Packing::for_key::<Paillier>(&pk, 6);
+        assert_eq!(packing.slots, (Paillier::plaintext_bits(&pk) / 7) as usize);
+        let x = [1, 9, 12, 18, 21];
+        let y = [1, 8, 12, 19, 21];
+        let nonces = [5, 7, 11][..packing.plaintexts_for(x.len())].to_vec();
+        let queries = packing.encrypt::<Paillier>(&pk, &x, &nonces);
+        assert_eq!(queries.len(), packing.plaintexts_for(x.len()));
+        let differences = packing.differences::<Paillier>(&pk, &queries, &y);
+        assert_eq!(
+            packing.decrypt_matches::<Paillier>(&sk, &differences, x.len()),
+            [true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn single_slot_packing_is_one_value_per_plaintext() {
+        let single = Packing::single(6);
+        assert_eq!(single.pack_offset(&[0, 5, 63]), [64, 69, 127]);
+        assert!(single.fits(&[63]));
+        assert!(!single.fits(&[64]));
+    }
+}
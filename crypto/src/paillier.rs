@@ -0,0 +1,319 @@
+use alloc::vec::Vec;
+
+use crate::arith::{extended_gcd, is_prime, mod_exp, mod_inv};
+use crate::encoding::{hex_encoding, read_version, versioned_encoding, ENCODING_VERSION, SECRET_KEY_ENCODING_VERSION};
+use crate::scheme::HomomorphicScheme;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaillierPublicKey {
+    pub n: u128,
+    pub g: u128,
+}
+
+/// The factorisation of `n` plus everything decryption needs modulo `p^2` and
+/// `q^2`, so each ciphertext costs two half-size exponentiations instead of one
+/// `mod_exp(c, lambda, n^2)`. Only `p` and `q` are encoded; the rest is
+/// recomputed by [`PaillierSecretKey::from_primes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaillierSecretKey {
+    pub p: u128,
+    pub q: u128,
+    pub n: u128,
+    p_sq: u128,
+    q_sq: u128,
+    /// `L_p(g^(p-1) mod p^2)^-1 mod p`
+    hp: u128,
+    /// `L_q(g^(q-1) mod q^2)^-1 mod q`
+    hq: u128,
+    /// `q^-1 mod p`, to recombine the two halves
+    q_inv: u128,
+}
+
+/// An element of `Z_{n^2}^*`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ciphertext(pub u128);
+
+versioned_encoding!(PaillierPublicKey, ENCODING_VERSION, { n, g });
+versioned_encoding!(Ciphertext, ENCODING_VERSION, { 0 });
+
+impl borsh::BorshSerialize for PaillierSecretKey {
+    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        borsh::BorshSerialize::serialize(&SECRET_KEY_ENCODING_VERSION, writer)?;
+        borsh::BorshSerialize::serialize(&self.p, writer)?;
+        borsh::BorshSerialize::serialize(&self.q, writer)
+    }
+}
+
+impl borsh::BorshDeserialize for PaillierSecretKey {
+    fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        read_version(reader, SECRET_KEY_ENCODING_VERSION, "PaillierSecretKey")?;
+        let p = <u128 as borsh::BorshDeserialize>::deserialize_reader(reader)?;
+        let q = <u128 as borsh::BorshDeserialize>::deserialize_reader(reader)?;
+        // `from_primes` would divide by zero or overflow on anything else
+        if !are_valid_primes(p, q) {
+            return Err(borsh::io::Error::new(
+                borsh::io::ErrorKind::InvalidData,
+                "PaillierSecretKey primes are not a valid key",
+            ));
+        }
+        Ok(PaillierSecretKey::from_primes(p, q))
+    }
+}
+
+hex_encoding!(PaillierSecretKey);
+
+impl PaillierPublicKey {
+    pub fn n_sq(&self) -> u128 {
+        self.n * self.n
+    }
+}
+
+impl PaillierSecretKey {
+    pub fn from_primes(p: u128, q: u128) -> Self {
+        let n = p * q;
+        let g = n + 1;
+        let (p_sq, q_sq) = (p * p, q * q);
+        let hp = mod_inv(l_function(mod_exp(g, p - 1, p_sq), p), p);
+        let hq = mod_inv(l_function(mod_exp(g, q - 1, q_sq), q), q);
+        let q_inv = mod_inv(q % p, p);
+
+        PaillierSecretKey { p, q, n, p_sq, q_sq, hp, hq, q_inv }
+    }
+
+    pub fn public_key(&self) -> PaillierPublicKey {
+        PaillierPublicKey { n: self.n, g: self.n + 1 }
+    }
+
+    /// `m mod p`
+    fn decrypt_p(&self, c: &Ciphertext) -> u128 {
+        (l_function(mod_exp(c.0 % self.p_sq, self.p - 1, self.p_sq), self.p) * self.hp) % self.p
+    }
+
+    /// `m mod q`
+    fn decrypt_q(&self, c: &Ciphertext) -> u128 {
+        (l_function(mod_exp(c.0 % self.q_sq, self.q - 1, self.q_sq), self.q) * self.hq) % self.q
+    }
+
+    /// Recombines `m mod p` and `m mod q` into `m mod n`.
+    fn crt(&self, m_p: u128, m_q: u128) -> u128 {
+        let h = ((m_p + self.p - m_q % self.p) % self.p * self.q_inv) % self.p;
+        m_q + self.q * h
+    }
+}
+
+// Helper for Encryption
+pub fn l_function(x: u128, n: u128) -> u128 {
+    (x - 1) / n
+}
+
+/// Bit length of freshly generated primes: `n^2` then stays below 2^60, well
+/// inside what `u128` products and Montgomery multiplication can handle. An `n`
+/// of about 30 bits factors instantly, so such keys are for demos only.
+pub const PRIME_BITS: u32 = 15;
+
+/// Whether `p` and `q` make a usable key: distinct primes of exactly
+/// `PRIME_BITS` bits with `gcd(pq, (p - 1)(q - 1)) = 1`.
+pub fn are_valid_primes(p: u128, q: u128) -> bool {
+    let bits = |x: u128| 128 - x.leading_zeros();
+    p != q
+        && bits(p) == PRIME_BITS
+        && bits(q) == PRIME_BITS
+        && is_prime(p)
+        && is_prime(q)
+        && extended_gcd((p * q) as i128, ((p - 1) * (q - 1)) as i128).0 == 1
+}
+
+pub fn prepare_key(p: u128, q: u128) -> (PaillierPublicKey, PaillierSecretKey) {
+    let sk = PaillierSecretKey::from_primes(p, q);
+    (sk.public_key(), sk)
+}
+
+/// [`encrypt_with_nonce`] with a fixed nonce (see [`HomomorphicScheme::encrypt`])
+pub fn encrypt(m: u128, pk: &PaillierPublicKey) -> Ciphertext {
+    encrypt_with_nonce(m, 3, pk)
+}
+
+/// `g^m * r^n mod n^2`, for a nonce `r` that is a unit modulo `n`
+pub fn encrypt_with_nonce(m: u128, r: u128, pk: &PaillierPublicKey) -> Ciphertext {
+    let PaillierPublicKey { n, g } = *pk;
+    let n_sq = n * n;
+    let g_m = if g == n + 1 {
+        // (n + 1)^m = 1 + m * n (mod n^2), no exponentiation needed
+        (1 + (m % n) * n) % n_sq
+    } else {
+        mod_exp(g, m, n_sq)
+    };
+    Ciphertext((g_m * mod_exp(r, n, n_sq)) % n_sq)
+}
+
+pub fn decrypt(c: &Ciphertext, sk: &PaillierSecretKey) -> u128 {
+    sk.crt(sk.decrypt_p(c), sk.decrypt_q(c))
+}
+
+pub fn decrypt_batch(cs: &[Ciphertext], sk: &PaillierSecretKey) -> Vec<u128> {
+    cs.iter().map(|c| decrypt(c, sk)).collect()
+}
+
+/// Paillier over `n = p * q`, with `g = n + 1`.
+pub struct Paillier;
+
+impl HomomorphicScheme for Paillier {
+    /// The two primes `(p, q)`
+    type KeyParams = (u128, u128);
+    type PublicKey = PaillierPublicKey;
+    type SecretKey = PaillierSecretKey;
+    type Ciphertext = Ciphertext;
+
+    fn keygen(&(p, q): &Self::KeyParams) -> (Self::PublicKey, Self::SecretKey) {
+        prepare_key(p, q)
+    }
+
+    /// Plaintexts live mod `n`, so anything below the highest power of two under `n` fits.
+    fn plaintext_bits(pk: &Self::PublicKey) -> u32 {
+        127 - pk.n.leading_zeros()
+    }
+
+    fn scalar_modulus(pk: &Self::PublicKey) -> u128 {
+        pk.n
+    }
+
+    fn encrypt(pk: &Self::PublicKey, m: u128) -> Self::Ciphertext {
+        encrypt(m, pk)
+    }
+
+    fn encrypt_with_nonce(pk: &Self::PublicKey, m: u128, r: u128) -> Self::Ciphertext {
+        encrypt_with_nonce(m, r, pk)
+    }
+
+    fn decrypt(sk: &Self::SecretKey, c: &Self::Ciphertext) -> u128 {
+        decrypt(c, sk)
+    }
+
+    fn decrypt_batch(sk: &Self::SecretKey, cs: &[Self::Ciphertext]) -> Vec<u128> {
+        decrypt_batch(cs, sk)
+    }
+
+    /// Zero iff both CRT halves are zero, so the `q` half is skipped for most non-matches.
+    fn is_zero(sk: &Self::SecretKey, c: &Self::Ciphertext) -> bool {
+        sk.decrypt_p(c) == 0 && sk.decrypt_q(c) == 0
+    }
+
+    fn add(pk: &Self::PublicKey, a: &Self::Ciphertext, b: &Self::Ciphertext) -> Self::Ciphertext {
+        Ciphertext((a.0 * b.0) % pk.n_sq())
+    }
+
+    fn scalar_mul(pk: &Self::PublicKey, c: &Self::Ciphertext, k: u128) -> Self::Ciphertext {
+        Ciphertext(mod_exp(c.0, k, pk.n_sq()))
+    }
+
+    fn negate(pk: &Self::PublicKey, c: &Self::Ciphertext) -> Self::Ciphertext {
+        Ciphertext(mod_exp(c.0, pk.n - 1, pk.n_sq()))
+    }
+
+    /// `c * r^n`, with `r` a unit mod `n`
+    fn rerandomize(pk: &Self::PublicKey, c: &Self::Ciphertext, r: u128) -> Self::Ciphertext {
+        Ciphertext((c.0 * mod_exp(r, pk.n, pk.n_sq())) % pk.n_sq())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::tests::TestRng;
+    use crate::blinding::is_unit;
+
+    /// Valid keys of `PRIME_BITS`-bit primes
+    fn keys(rng: &mut TestRng, count: usize) -> Vec<PaillierSecretKey> {
+        let mut random_prime = || loop {
+            let candidate = rng.below(1 << (PRIME_BITS - 1)) | (1 << (PRIME_BITS - 1)) | 1;
+            if is_prime(candidate) {
+                return candidate;
+            }
+        };
+        let mut keys = Vec::new();
+        while keys.len() < count {
+            let (p, q) = (random_prime(), random_prime());
+            if are_valid_primes(p, q) {
+                keys.push(PaillierSecretKey::from_primes(p, q));
+            }
+        }
+        keys
+    }
+
+    fn random_unit(rng: &mut TestRng, n: u128) -> u128 {
+        loop {
+            let r = rng.below(n);
+            if is_unit(r, n) {
+                return r;
+            }
+        }
+    }
+
+    /// `c^lambda` decryption, without the CRT
+    fn decrypt_textbook(c: &Ciphertext, sk: &PaillierSecretKey) -> u128 {
+        let (n, n_sq) = (sk.n, sk.n * sk.n);
+        let lambda = (sk.p - 1) * (sk.q - 1);
+        let mu = mod_inv(l_function(mod_exp(n + 1, lambda, n_sq), n), n);
+        (l_function(mod_exp(c.0, lambda, n_sq), n) * mu) % n
+    }
+
+    #[test]
+    fn crt_decryption_round_trips() {
+        let mut rng = TestRng(0x5eed_0001);
+        for sk in keys(&mut rng, 20) {
+            let pk = sk.public_key();
+            for m in [0, 1, pk.n - 1, rng.below(pk.n), rng.below(pk.n)] {
+                let c = encrypt_with_nonce(m, random_unit(&mut rng, pk.n), &pk);
+                assert_eq!(decrypt(&c, &sk), m);
+                assert_eq!(decrypt_textbook(&c, &sk), m);
+                assert_eq!(Paillier::is_zero(&sk, &c), m == 0);
+            }
+        }
+    }
+
+    #[test]
+    fn nonces_randomize_ciphertexts() {
+        let mut rng = TestRng(0x5eed_0002);
+        let sk = keys(&mut rng, 1)[0];
+        let pk = sk.public_key();
+        let a = encrypt_with_nonce(42, random_unit(&mut rng, pk.n), &pk);
+        let b = encrypt_with_nonce(42, random_unit(&mut rng, pk.n), &pk);
+        assert_ne!(a, b);
+        assert_eq!(decrypt_batch(&[a, b], &sk), [42, 42]);
+        assert_eq!(encrypt(42, &pk), encrypt_with_nonce(42, 3, &pk));
+    }
+
+    #[test]
+    fn homomorphic_operations_decrypt_mod_n() {
+        let mut rng = TestRng(0x5eed_0003);
+        let sk = keys(&mut rng, 1)[0];
+        let pk = sk.public_key();
+        for _ in 0..50 {
+            let (x, y, k) = (rng.below(pk.n), rng.below(pk.n), rng.below(pk.n));
+            let (cx, cy) = (Paillier::encrypt(&pk, x), Paillier::encrypt(&pk, y));
+            assert_eq!(decrypt(&Paillier::add(&pk, &cx, &cy), &sk), (x + y) % pk.n);
+            assert_eq!(decrypt(&Paillier::sub(&pk, &cx, &cy), &sk), (x + pk.n - y) % pk.n);
+            assert_eq!(decrypt(&Paillier::scalar_mul(&pk, &cx, k), &sk), (x * k) % pk.n);
+            let r = random_unit(&mut rng, pk.n);
+            assert_eq!(decrypt(&Paillier::rerandomize(&pk, &cx, r), &sk), x);
+        }
+    }
+
+    #[test]
+    fn secret_keys_only_decode_from_valid_primes() {
+        let (_, sk) = prepare_key(18443, 17041);
+        assert_eq!(PaillierSecretKey::from_hex(&sk.to_hex()), Ok(sk));
+
+        let encode = |p: u128, q: u128| {
+            let mut bytes = alloc::vec![SECRET_KEY_ENCODING_VERSION];
+            bytes.extend(p.to_le_bytes());
+            bytes.extend(q.to_le_bytes());
+            hex::encode(bytes)
+        };
+        assert!(PaillierSecretKey::from_hex(&alloc::format!("02{}", "00".repeat(32))).is_err());
+        // Composite, equal, and too large
+        assert!(PaillierSecretKey::from_hex(&encode(18443 * 3, 17041)).is_err());
+        assert!(PaillierSecretKey::from_hex(&encode(18443, 18443)).is_err());
+        assert!(PaillierSecretKey::from_hex(&encode(u128::MAX, 17041)).is_err());
+    }
+}
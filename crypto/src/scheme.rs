@@ -0,0 +1,69 @@
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display};
+
+/// An additively homomorphic encryption scheme over small integer plaintexts.
+///
+/// The PSI only needs to combine ciphertexts and test whether a difference
+/// decrypts to zero, so every backend exposes the same handful of operations.
+pub trait HomomorphicScheme {
+    /// Parameters a keypair is derived from (primes, generator, secret exponent...)
+    type KeyParams;
+    type PublicKey: Clone + Debug;
+    type SecretKey: Clone + Debug;
+    type Ciphertext: Clone + Debug + Display + PartialEq;
+
+    fn keygen(params: &Self::KeyParams) -> (Self::PublicKey, Self::SecretKey);
+
+    /// How many bits a plaintext can hold and still decrypt to the same value.
+    fn plaintext_bits(pk: &Self::PublicKey) -> u32;
+
+    /// Scalars and nonces are taken modulo this: the plaintext modulus for
+    /// Paillier, the group order for ElGamal.
+    fn scalar_modulus(pk: &Self::PublicKey) -> u128;
+
+    /// Enc(m) with a fixed nonce, so equal plaintexts give equal ciphertexts.
+    /// Only for values that are re-randomized before anyone sees them, such as
+    /// the server's side of a blinded difference.
+    fn encrypt(pk: &Self::PublicKey, m: u128) -> Self::Ciphertext;
+
+    /// Enc(m) with nonce `r`, a unit modulo [`scalar_modulus`](Self::scalar_modulus).
+    fn encrypt_with_nonce(pk: &Self::PublicKey, m: u128, r: u128) -> Self::Ciphertext;
+
+    fn decrypt(sk: &Self::SecretKey, c: &Self::Ciphertext) -> u128;
+
+    fn decrypt_batch(sk: &Self::SecretKey, cs: &[Self::Ciphertext]) -> Vec<u128> {
+        cs.iter().map(|c| Self::decrypt(sk, c)).collect()
+    }
+
+    /// Enc(a) , Enc(b) -> Enc(a + b)
+    fn add(pk: &Self::PublicKey, a: &Self::Ciphertext, b: &Self::Ciphertext) -> Self::Ciphertext;
+
+    /// Enc(a) , k -> Enc(k * a)
+    fn scalar_mul(pk: &Self::PublicKey, c: &Self::Ciphertext, k: u128) -> Self::Ciphertext;
+
+    /// Enc(a) -> Enc(-a)
+    fn negate(pk: &Self::PublicKey, c: &Self::Ciphertext) -> Self::Ciphertext;
+
+    /// Folds a fresh encryption of zero with nonce `r` into `c`, so the result
+    /// can't be linked to the ciphertexts it was computed from.
+    fn rerandomize(pk: &Self::PublicKey, c: &Self::Ciphertext, r: u128) -> Self::Ciphertext;
+
+    /// Enc(a) , k , r -> Enc(k * a), re-randomized with `r`
+    fn blind(pk: &Self::PublicKey, c: &Self::Ciphertext, k: u128, r: u128) -> Self::Ciphertext {
+        Self::rerandomize(pk, &Self::scalar_mul(pk, c, k), r)
+    }
+
+    /// Enc(a) , Enc(b) -> Enc(a - b)
+    fn sub(pk: &Self::PublicKey, a: &Self::Ciphertext, b: &Self::Ciphertext) -> Self::Ciphertext {
+        Self::add(pk, a, &Self::negate(pk, b))
+    }
+
+    /// Whether `c` encrypts zero. Schemes where a full decryption is expensive override this.
+    fn is_zero(sk: &Self::SecretKey, c: &Self::Ciphertext) -> bool {
+        Self::decrypt(sk, c) == 0
+    }
+
+    fn is_zero_batch(sk: &Self::SecretKey, cs: &[Self::Ciphertext]) -> Vec<bool> {
+        cs.iter().map(|c| Self::is_zero(sk, c)).collect()
+    }
+}
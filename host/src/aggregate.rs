@@ -0,0 +1,260 @@
+// host/src/aggregate.rs
+
+//! Folding many proofs into one proof transaction.
+//!
+//! At a busy meetup every submission would otherwise send a proof transaction
+//! of its own. With `--aggregate <n>`, proofs of the Meetup guest sent to the
+//! node at `--host` queue up instead, and every `n` of them, or whatever queued
+//! within `--aggregate-wait`, are folded into one succinct receipt with RISC
+//! Zero composition: the aggregator guest `env::verify`s each receipt, which the
+//! prover resolves from the receipts added as assumptions, and commits the
+//! image ID and journal of each. The aggregate goes out as a single proof
+//! transaction of [`AGGREGATE_CONTRACT`], and [`records`] keeps which blob
+//! transactions each one covers.
+//!
+//! [`enable`] registers [`AGGREGATE_CONTRACT`] with the aggregator guest as its
+//! program, or checks that the node already runs it under that name.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use client_sdk::rest_client::NodeApiHttpClient;
+use methods::{AGGREGATOR_ELF, AGGREGATOR_ID, GUEST_ID};
+use risc0_zkvm::{default_executor, default_prover, ExecutorEnv, ProverOpts, Receipt};
+use sdk::api::APIRegisterContract;
+use sdk::{Contract, ProgramId, ProofData, ProofTransaction, StateDigest, TxHash};
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::prover::fake_receipt;
+
+/// Contract the aggregates are sent to
+pub const AGGREGATE_CONTRACT: &str = "risc0-recursion";
+
+/// What the aggregator guest commits: the image ID and journal of each receipt
+/// it folded, in order
+pub type AggregateJournal = Vec<([u32; 8], Vec<u8>)>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AggregationConfig {
+    /// Proofs folded into one aggregate at most
+    pub max_proofs: usize,
+    /// How long the first queued proof waits for others
+    pub max_wait: Duration,
+}
+
+/// An aggregate sent to a node.
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregateRecord {
+    pub host: String,
+    pub proof_tx_hash: String,
+    /// Blob transactions with a proof in the aggregate, in the order they were folded
+    pub blob_tx_hashes: Vec<String>,
+    pub proofs: usize,
+}
+
+struct Queued {
+    blob_tx_hash: TxHash,
+    proof: ProofData,
+    fake: bool,
+    /// Gets the hash of the aggregate's proof transaction, or why it wasn't sent
+    done: oneshot::Sender<Result<TxHash, String>>,
+}
+
+/// Nodes whose proofs are aggregated
+static CONFIGS: LazyLock<Mutex<HashMap<String, AggregationConfig>>> = LazyLock::new(Default::default);
+/// Proofs waiting for the next aggregate, per node
+static QUEUES: LazyLock<Mutex<HashMap<String, Vec<Queued>>>> = LazyLock::new(Default::default);
+static RECORDS: LazyLock<Mutex<Vec<AggregateRecord>>> = LazyLock::new(Default::default);
+
+/// Aggregates the proofs of every later submission to the node at `host`,
+/// once its [`AGGREGATE_CONTRACT`] runs the aggregator guest.
+pub async fn enable(host: &str, config: AggregationConfig) -> Result<()> {
+    ensure_aggregate_contract(host).await?;
+    CONFIGS.lock().unwrap().insert(host.to_string(), config);
+    Ok(())
+}
+
+/// Registers [`AGGREGATE_CONTRACT`] with [`AGGREGATOR_ID`] as its program if
+/// the node doesn't know it, and fails if it runs another program.
+async fn ensure_aggregate_contract(host: &str) -> Result<()> {
+    let program_id = ProgramId(sdk::to_u8_array(&AGGREGATOR_ID).to_vec());
+    let url = format!("{}/v1/contract/{}", host.trim_end_matches('/'), AGGREGATE_CONTRACT);
+    let response = reqwest::get(&url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        let client = NodeApiHttpClient::new(host.to_string())?;
+        let registration = APIRegisterContract {
+            verifier: "risc0".into(),
+            program_id,
+            // Aggregates carry no state of their own
+            state_digest: StateDigest(Vec::new()),
+            contract_name: AGGREGATE_CONTRACT.into(),
+        };
+        let tx_hash = client.register_contract(&registration).await?;
+        println!("✅ Registered {} for aggregates. Tx hash: {}", AGGREGATE_CONTRACT, tx_hash);
+        return Ok(());
+    }
+    let contract: Contract = response
+        .error_for_status()?
+        .json()
+        .await
+        .with_context(|| format!("Unexpected response from {}", url))?;
+    ensure!(
+        contract.program_id.0 == program_id.0,
+        "{} on {} runs another program than the aggregator guest",
+        AGGREGATE_CONTRACT,
+        host
+    );
+    Ok(())
+}
+
+fn config(host: &str) -> Option<AggregationConfig> {
+    CONFIGS.lock().unwrap().get(host).copied()
+}
+
+pub fn enabled(host: &str) -> bool {
+    config(host).is_some()
+}
+
+/// The aggregates sent so far, oldest first
+pub fn records() -> Vec<AggregateRecord> {
+    RECORDS.lock().unwrap().clone()
+}
+
+/// Queues the proofs of the blobs of `blob_tx_hash`, in blob order, for the
+/// next aggregate sent to `host`, and returns the hash of its proof
+/// transaction once it is sent. `fake` tells fake receipts, which only make a
+/// fake aggregate.
+pub async fn submit(host: &str, blob_tx_hash: &TxHash, proofs: Vec<ProofData>, fake: bool) -> Result<TxHash> {
+    let config = config(host).with_context(|| format!("Proofs sent to {} are not aggregated", host))?;
+    let mut receivers = Vec::new();
+    let (full, first) = {
+        let mut queues = QUEUES.lock().unwrap();
+        let queue = queues.entry(host.to_string()).or_default();
+        let first = queue.is_empty();
+        for proof in proofs {
+            let (done, receiver) = oneshot::channel();
+            queue.push(Queued {
+                blob_tx_hash: blob_tx_hash.clone(),
+                proof,
+                fake,
+                done,
+            });
+            receivers.push(receiver);
+        }
+        (queue.len() >= config.max_proofs, first)
+    };
+    let host = host.to_string();
+    if full {
+        tokio::spawn(flush(host));
+    } else if first {
+        let max_wait = config.max_wait;
+        tokio::spawn(async move {
+            tokio::time::sleep(max_wait).await;
+            flush(host).await
+        });
+    }
+
+    let mut proof_tx_hash = None;
+    for receiver in receivers {
+        let sent = receiver.await.context("The aggregate was dropped")?;
+        proof_tx_hash = Some(sent.map_err(anyhow::Error::msg)?);
+    }
+    proof_tx_hash.context("No proof to aggregate")
+}
+
+/// Sends everything queued for `host`, `max_proofs` at a time.
+async fn flush(host: String) {
+    let max_proofs = config(&host).map_or(usize::MAX, |c| c.max_proofs.max(1));
+    loop {
+        let batch: Vec<Queued> = {
+            let mut queues = QUEUES.lock().unwrap();
+            let queue = queues.entry(host.clone()).or_default();
+            let n = queue.len().min(max_proofs);
+            queue.drain(..n).collect()
+        };
+        if batch.is_empty() {
+            return;
+        }
+        match send_aggregate(&host, &batch).await {
+            Ok(proof_tx_hash) => {
+                let mut blob_tx_hashes: Vec<String> = Vec::new();
+                for queued in &batch {
+                    if !blob_tx_hashes.contains(&queued.blob_tx_hash.0) {
+                        blob_tx_hashes.push(queued.blob_tx_hash.0.clone());
+                    }
+                }
+                RECORDS.lock().unwrap().push(AggregateRecord {
+                    host: host.clone(),
+                    proof_tx_hash: proof_tx_hash.0.clone(),
+                    blob_tx_hashes,
+                    proofs: batch.len(),
+                });
+                for queued in batch {
+                    let _ = queued.done.send(Ok(proof_tx_hash.clone()));
+                }
+            }
+            Err(err) => {
+                for queued in batch {
+                    let _ = queued.done.send(Err(format!("Aggregate not sent: {:#}", err)));
+                }
+            }
+        }
+    }
+}
+
+async fn send_aggregate(host: &str, batch: &[Queued]) -> Result<TxHash> {
+    let proofs: Vec<ProofData> = batch.iter().map(|queued| queued.proof.clone()).collect();
+    let fake = batch.iter().any(|queued| queued.fake);
+    // Proving the aggregate takes a while, keep it off the async workers
+    let aggregate = tokio::task::spawn_blocking(move || fold(&proofs, fake)).await??;
+
+    let client = NodeApiHttpClient::new(host.to_string())?;
+    let proof_tx = ProofTransaction {
+        proof: aggregate,
+        contract_name: AGGREGATE_CONTRACT.into(),
+    };
+    let proof_tx_hash = client.send_tx_proof(&proof_tx).await?;
+    println!("✅ Aggregate of {} proofs sent. Tx hash: {}", batch.len(), proof_tx_hash);
+    Ok(proof_tx_hash)
+}
+
+/// Folds receipts of the Meetup guest into one succinct receipt of the
+/// aggregator guest. Fake receipts make a fake aggregate, which only verifies
+/// with `RISC0_DEV_MODE=1`.
+pub fn fold(proofs: &[ProofData], fake: bool) -> Result<ProofData> {
+    let mut journal: AggregateJournal = Vec::new();
+    let mut env = ExecutorEnv::builder();
+    for proof in proofs {
+        let receipt: Receipt = borsh::from_slice(&proof.0).context("Could not decode receipt")?;
+        journal.push((GUEST_ID, receipt.journal.bytes.clone()));
+        env.add_assumption(receipt);
+    }
+    let env = env.write(&journal)?.build()?;
+    let receipt = if fake {
+        let session = default_executor().execute(env, AGGREGATOR_ELF)?;
+        fake_receipt(AGGREGATOR_ID, session.journal.bytes)
+    } else {
+        default_prover()
+            .prove_with_opts(env, AGGREGATOR_ELF, &ProverOpts::succinct())?
+            .receipt
+    };
+    ensure!(
+        decode_aggregate(&receipt, !fake)? == journal,
+        "The aggregate does not carry the folded journals"
+    );
+    Ok(ProofData(borsh::to_vec(&receipt)?))
+}
+
+/// The journals folded into `receipt`, verifying it against the aggregator
+/// guest first unless `verify_receipt` is false.
+pub fn decode_aggregate(receipt: &Receipt, verify_receipt: bool) -> Result<AggregateJournal> {
+    if verify_receipt {
+        receipt
+            .verify(AGGREGATOR_ID)
+            .context("Receipt does not verify against the aggregator guest")?;
+    }
+    receipt.journal.decode().context("Could not decode the aggregate's journal")
+}
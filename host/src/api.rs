@@ -1,23 +1,38 @@
 // host/src/api.rs
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use contract::Meetup;
-use contract::MeetupAction;
+use contract::{MeetupAction, MeetupEvent};
+use contract::{catalog, interest_packing, with_scheme, DifferencesInput, EncryptionInput, InterestLayout, INTEREST_BITS};
+use contract::{Ciphertexts, PublicKey, TaggedScheme};
 use client_sdk::rest_client::NodeApiHttpClient;
-use client_sdk::helpers::risc0::Risc0Prover;
-use sdk::{ContractInput, HyleContract, ProofTransaction, BlobTransaction, BlobIndex, ProgramId};
+use sdk::{Blob, ProofData, ProofTransaction, BlobTransaction, ProgramId, StateDigest, TxHash};
 use sdk::Digestable;
-use methods::{GUEST_ELF, GUEST_ID};
+use methods::GUEST_ID;
+use crypto::Blinding;
+
+use crate::aggregate;
+use crate::pending::{self, BlobStep, PendingTx};
+use crate::proof::{decode_event, verify_transition_proof};
+use crate::prover::Prover;
+use crate::psi;
+
+/// The identity this host sends transactions as.
+pub fn identity(contract_name: &str) -> String {
+    format!("none.{}", contract_name)
+}
+
+/// The state the contract is registered with.
+pub fn initial_state() -> Meetup {
+    Meetup::new()
+}
 
 pub async fn register_contract(
     host: &str,
     contract_name: &str,
 ) -> Result<String> {
     let client = NodeApiHttpClient::new(host.to_string())?;
-    let initial_state = Meetup {
-        merkle_roots: Vec::new(),
-        encrypted_message_hash: String::new(),
-    };
+    let initial_state = initial_state();
 
     let res = client.register_contract(
         &sdk::api::APIRegisterContract {
@@ -27,124 +42,712 @@ pub async fn register_contract(
             contract_name: contract_name.into(),
         }
     ).await?;
-    
+
     Ok(res.to_string())
 }
 
-pub async fn post_root(
+/// The contract state as last settled on the node.
+pub async fn get_state(host: &str, contract_name: &str) -> Result<Meetup> {
+    let client = NodeApiHttpClient::new(host.to_string())?;
+    Ok(Meetup::try_from(client.get_contract(&contract_name.into()).await?.state)?)
+}
+
+/// Steps of [`submit_action`] reported to `ActionHooks::stage`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// Executing the action locally against the predicted state
+    Executing,
+    /// Blob sent, generating the proof
+    Proving,
+    /// Proof sent, waiting for the node to settle it
+    Submitted {
+        proof_tx_hash: String,
+        /// What the proven actions did
+        events: Vec<MeetupEvent>,
+    },
+}
+
+/// Extension points of [`submit_action`]. All of them default to doing nothing.
+pub trait ActionHooks {
+    /// Runs on the predicted state before anything is sent, to reject an action
+    /// with a clearer error than the contract's (or one it can't check).
+    fn validate(&self, _state: &Meetup, _action: &MeetupAction) -> Result<()> {
+        Ok(())
+    }
+
+    /// Runs on the proof before it is sent in the proof transaction.
+    fn post_process(&self, proof: ProofData) -> Result<ProofData> {
+        Ok(proof)
+    }
+
+    /// Called when the pipeline reaches `stage`.
+    fn stage(&self, _stage: &Stage) {}
+}
+
+/// No validation, proofs sent as-is
+pub struct NoHooks;
+
+impl ActionHooks for NoHooks {}
+
+impl<T: ActionHooks + ?Sized> ActionHooks for &T {
+    fn validate(&self, state: &Meetup, action: &MeetupAction) -> Result<()> {
+        (**self).validate(state, action)
+    }
+
+    fn post_process(&self, proof: ProofData) -> Result<ProofData> {
+        (**self).post_process(proof)
+    }
+
+    fn stage(&self, stage: &Stage) {
+        (**self).stage(stage)
+    }
+}
+
+/// Runs `A`'s hooks, then `B`'s.
+impl<A: ActionHooks, B: ActionHooks> ActionHooks for (A, B) {
+    fn validate(&self, state: &Meetup, action: &MeetupAction) -> Result<()> {
+        self.0.validate(state, action)?;
+        self.1.validate(state, action)
+    }
+
+    fn post_process(&self, proof: ProofData) -> Result<ProofData> {
+        self.1.post_process(self.0.post_process(proof)?)
+    }
+
+    fn stage(&self, stage: &Stage) {
+        self.0.stage(stage);
+        self.1.stage(stage);
+    }
+}
+
+/// What [`submit_actions`] sent.
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub blob_tx_hash: TxHash,
+    /// The proof transaction of each blob, in blob order; the same aggregate's
+    /// for all of them when aggregating
+    pub proof_tx_hashes: Vec<TxHash>,
+    /// The proofs as sent, after `ActionHooks::post_process`, in blob order
+    pub proofs: Vec<ProofData>,
+    /// What each blob did, as its proof reports it
+    pub events: Vec<MeetupEvent>,
+    /// The contract state once the proven transitions are applied
+    pub expected_state: StateDigest,
+}
+
+impl Submission {
+    /// The proof of the first blob, the only one of a single action
+    pub fn proof(&self) -> &ProofData {
+        &self.proofs[0]
+    }
+}
+
+/// Sends `action` alone in a blob transaction; see [`submit_actions`].
+pub async fn submit_action(
     host: &str,
     contract_name: &str,
-    interests: String,
-) -> Result<String> {
+    prover: &Prover,
+    action: MeetupAction,
+    private_input: Vec<u8>,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    submit_actions(host, contract_name, prover, vec![(action, private_input)], hooks).await
+}
+
+/// Sends `actions` as the blobs of one blob transaction, proves each with its
+/// private input against the state the previous one leaves behind, starting
+/// from the state the node will be in once this host's pending transactions
+/// settled (see `pending`), and sends the proofs.
+///
+/// Every contract action goes through here; an action only needs an adapter
+/// building the action and its private input.
+pub async fn submit_actions(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    actions: Vec<(MeetupAction, Vec<u8>)>,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    anyhow::ensure!(!actions.is_empty(), "Nothing to submit");
     // Initialize the client and identity.
     let client = NodeApiHttpClient::new(host.to_string())?;
-    let identity = format!("none.{}", contract_name);
+    let identity = identity(contract_name);
+
+    // Predict the initial state from the node's and our pending transactions.
+    hooks.stage(&Stage::Executing);
+    let slot = pending::reserve(host, contract_name).await;
+    let initial_state = slot.predicted_state(&client).await?;
+    let proven_on = initial_state.as_digest();
 
-    // Fetch the initial state from the node.
-    let mut initial_state: Meetup = client
-        .get_contract(&contract_name.into())
-        .await?
-        .state
-        .into();
+    // Check every action on the state it will run on before anything is sent.
+    let blobs: Vec<Blob> = actions.iter().map(|(action, _)| action.as_blob(contract_name)).collect();
+    let private_inputs: Vec<Vec<u8>> = actions.iter().map(|(_, input)| input.clone()).collect();
+    let mut state = initial_state.clone();
+    for (index, (action, private_input)) in actions.iter().enumerate() {
+        hooks.validate(&state, action)?;
+        pending::execute_blob(&identity, &TxHash(String::new()), &blobs, index, private_input, &mut state)?;
+    }
 
     // ---- Build and send the blob transaction ----
-    let action = MeetupAction::PostRoot {};
-    let blobs = vec![action.as_blob(contract_name)];
     let blob_tx = BlobTransaction::new(identity.clone(), blobs.clone());
     let blob_tx_hash = client.send_tx_blob(&blob_tx).await?;
     println!("✅ Blob tx sent. Tx hash: {}", blob_tx_hash);
 
-    // ---- Prove the state transition ----
-    let inputs = ContractInput {
-        state: initial_state.as_bytes()?,
-        identity: identity.clone().into(),
-        tx_hash: blob_tx_hash.clone(),
-        private_input: interests.as_bytes().to_vec(),
-        tx_ctx: None,
-        blobs: blobs.clone(),
-        index: BlobIndex(0),
-    };
+    // ---- Execute each blob on the state the previous one left ----
+    let (steps, next_state) = pending::execute_blobs(&identity, &blob_tx_hash, &blobs, &private_inputs, initial_state)?;
+    println!("🚀 Executed {} blob(s)", steps.len());
 
-    let (program_outputs, _, _) = initial_state
-        .execute(&inputs)
-        .map_err(|e| anyhow::anyhow!(e))?;
-    println!("🚀 Executed: {}", program_outputs);
+    // Later submissions build on this one from here on
+    slot.push(PendingTx {
+        blob_tx_hash: blob_tx_hash.clone(),
+        identity,
+        blobs,
+        private_inputs,
+        proven_on,
+        prover: prover.clone(),
+    });
+    drop(slot);
 
-    // Create the prover and generate the proof.
-    let prover = Risc0Prover::new(GUEST_ELF);
-    let proof = prover.prove(inputs).await?;
+    // ---- Prove each blob and send the proofs ----
+    hooks.stage(&Stage::Proving);
+    let sent = prove_and_send(host, contract_name, prover, &blob_tx_hash, steps, hooks).await?;
+    hooks.stage(&Stage::Submitted {
+        proof_tx_hash: sent.proof_tx_hashes.last().map(|h| h.to_string()).unwrap_or_default(),
+        events: sent.events.clone(),
+    });
 
-    // Build and send the proof transaction.
-    let proof_tx = ProofTransaction {
-        proof,
-        contract_name: contract_name.into(),
-    };
+    Ok(Submission {
+        blob_tx_hash,
+        proof_tx_hashes: sent.proof_tx_hashes,
+        proofs: sent.proofs,
+        events: sent.events,
+        expected_state: next_state.as_digest(),
+    })
+}
+
+/// What [`prove_and_send`] sent, per blob in blob order.
+pub(crate) struct SentProofs {
+    pub proof_tx_hashes: Vec<TxHash>,
+    pub proofs: Vec<ProofData>,
+    pub events: Vec<MeetupEvent>,
+}
+
+/// Proves the blobs of `blob_tx_hash`, checks each proof is of the transition
+/// its step executed locally, and sends them: one proof transaction per blob,
+/// or their share of an aggregate when aggregation is on (see `aggregate`).
+/// The events come from the proven outputs.
+pub(crate) async fn prove_and_send(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    blob_tx_hash: &TxHash,
+    steps: Vec<BlobStep>,
+    hooks: &impl ActionHooks,
+) -> Result<SentProofs> {
+    let mut proofs = Vec::new();
+    let mut events = Vec::new();
+    for step in steps {
+        let proof = prover.prove(step.inputs.clone()).await?;
+        let output = verify_transition_proof(&proof, !prover.fakes_receipts(), &step)
+            .context("Refusing to submit the prover's proof")?;
+        let event = decode_event(&output)?.context("A successful proof without event")?;
+        println!("📣 {:?}", event);
+        events.push(event);
+        proofs.push(hooks.post_process(proof)?);
+    }
 
-    let proof_tx_hash = client.send_tx_proof(&proof_tx).await?;
-    println!("✅ Proof tx sent. Tx hash: {}", proof_tx_hash);
+    if aggregate::enabled(host) {
+        let proof_tx_hash = aggregate::submit(host, blob_tx_hash, proofs.clone(), prover.fakes_receipts()).await?;
+        return Ok(SentProofs {
+            proof_tx_hashes: vec![proof_tx_hash; proofs.len()],
+            proofs,
+            events,
+        });
+    }
+    let client = NodeApiHttpClient::new(host.to_string())?;
+    let mut proof_tx_hashes = Vec::new();
+    for proof in &proofs {
+        let proof_tx = ProofTransaction {
+            proof: proof.clone(),
+            contract_name: contract_name.into(),
+        };
+        let proof_tx_hash = client.send_tx_proof(&proof_tx).await?;
+        println!("✅ Proof tx sent. Tx hash: {}", proof_tx_hash);
+        proof_tx_hashes.push(proof_tx_hash);
+    }
+    Ok(SentProofs {
+        proof_tx_hashes,
+        proofs,
+        events,
+    })
+}
 
-    Ok(proof_tx_hash.to_string())
+pub async fn post_root(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    interests: String,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    submit_action(
+        host,
+        contract_name,
+        prover,
+        MeetupAction::PostRoot,
+        interests.into_bytes(),
+        hooks,
+    ).await
+}
+
+/// Rejects ciphertexts under a key other than the one registered for the identity,
+/// or when none is.
+struct RegisteredKey {
+    identity: String,
+}
+
+impl ActionHooks for RegisteredKey {
+    fn validate(&self, state: &Meetup, action: &MeetupAction) -> Result<()> {
+        let MeetupAction::AddEncryption { public_key, .. } = action else {
+            return Ok(());
+        };
+        match state.public_keys.get(&self.identity) {
+            Some(registered) if registered == public_key => Ok(()),
+            Some(_) => anyhow::bail!("{} has another key registered; rotate it first", self.identity),
+            None => anyhow::bail!("{} has no key registered; register it first", self.identity),
+        }
+    }
+}
+
+/// The `AddEncryption` of `interests` under `public_key`, in that key's scheme
+/// and packed as `layout` says, with its private input.
+pub fn add_encryption_action(public_key: &PublicKey, layout: InterestLayout, interests: &str) -> Result<(MeetupAction, Vec<u8>)> {
+    let messages = interests
+        .split_whitespace()
+        .map(|x| x.parse::<u128>())
+        .collect::<Result<Vec<u128>, _>>()?;
+    if !catalog::are_valid_answers(&messages) {
+        anyhow::bail!("Interests must be answers to the catalog questions, in question order");
+    }
+    if !interest_packing().fits(&messages) {
+        anyhow::bail!("Interests must fit in {} bits", INTEREST_BITS);
+    }
+    let (ciphertexts, nonces) = with_scheme!(public_key.kind(), S => {
+        let pk = S::key(public_key).expect("the key is of its own scheme");
+        let packing = layout.packing::<S>(pk);
+        let nonces = psi::sample_units::<S>(pk, packing.plaintexts_for(messages.len()));
+        (S::tag_ciphertexts(packing.encrypt::<S>(pk, &messages, &nonces)), nonces)
+    });
+
+    let action = MeetupAction::AddEncryption {
+        public_key: *public_key,
+        ciphertexts,
+        layout,
+    };
+    let private_input = borsh::to_vec(&EncryptionInput {
+        interests: messages,
+        nonces,
+    })?;
+    Ok((action, private_input))
 }
 
 pub async fn post_enc(
     host: &str,
     contract_name: &str,
-    p: u128,
-    q: u128,
+    prover: &Prover,
+    public_key: &PublicKey,
+    layout: InterestLayout,
     interests: String,
-) -> Result<String> {
-    // Initialize the client and identity.
-    let client = NodeApiHttpClient::new(host.to_string())?;
-    let identity = format!("none.{}", contract_name);
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    let (action, private_input) = add_encryption_action(public_key, layout, &interests)?;
+    let registered = RegisteredKey {
+        identity: identity(contract_name),
+    };
+    // The proof of the submission also lets the PSI server check the ciphertexts before using them.
+    submit_action(host, contract_name, prover, action, private_input, &(registered, hooks)).await
+}
 
-    // Fetch the initial state from the node.
-    let mut initial_state: Meetup = client
-        .get_contract(&contract_name.into())
-        .await?
-        .state
-        .into();
+/// Submits several actions in one blob transaction, e.g. a `PostRoot`, a
+/// `RegisterPublicKey` and an `AddEncryption` under the key it registers.
+pub async fn submit_batch(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    actions: Vec<(MeetupAction, Vec<u8>)>,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    let registered = RegisteredKey {
+        identity: identity(contract_name),
+    };
+    submit_actions(host, contract_name, prover, actions, &(registered, hooks)).await
+}
 
-    // ---- Build and send the blob transaction ----
-    let action = MeetupAction::AddEncryption {};
-    let blobs = vec![action.as_blob(contract_name)];
-    let blob_tx = BlobTransaction::new(identity.clone(), blobs.clone());
-    let blob_tx_hash = client.send_tx_blob(&blob_tx).await?;
-    println!("✅ Blob tx sent. Tx hash: {}", blob_tx_hash);
+pub async fn register_key(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    public_key: &PublicKey,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    let action = MeetupAction::RegisterPublicKey {
+        public_key: *public_key,
+    };
+    submit_action(host, contract_name, prover, action, vec![], hooks).await
+}
 
-    // ---- Prove the state transition ----
-    let mut private_input = p.to_string();
-    private_input.push_str(" ");
-    private_input.push_str(q.to_string().as_str());
-    private_input.push_str(" ");
-    private_input.push_str(interests.as_str());
-
-    let inputs = ContractInput {
-        state: initial_state.as_bytes()?,
-        identity: identity.clone().into(),
-        tx_hash: blob_tx_hash.clone(),
-        private_input: private_input.as_bytes().to_vec(),
-        tx_ctx: None,
-        blobs: blobs.clone(),
-        index: BlobIndex(0),
+/// Replaces this identity's key; its ciphertexts have to be posted again afterwards.
+pub async fn rotate_key(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    new_public_key: &PublicKey,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    let action = MeetupAction::RotateKey {
+        new_public_key: *new_public_key,
     };
+    submit_action(host, contract_name, prover, action, vec![], hooks).await
+}
 
-    let (program_outputs, _, _) = initial_state
-        .execute(&inputs)
-        .map_err(|e| anyhow::anyhow!(e))?;
-    println!("🚀 Executed: {}", program_outputs);
+pub async fn revoke_key(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    submit_action(host, contract_name, prover, MeetupAction::RevokeKey, vec![], hooks).await
+}
 
-    // Create the prover and generate the proof.
-    let prover = Risc0Prover::new(GUEST_ELF);
-    let proof = prover.prove(inputs).await?;
+/// Upgrades the contract state to the layout this build writes (see `contract::state`).
+pub async fn migrate_state(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    submit_action(host, contract_name, prover, MeetupAction::Migrate, vec![], hooks).await
+}
 
-    // Build and send the proof transaction.
-    let proof_tx = ProofTransaction {
-        proof,
-        contract_name: contract_name.into(),
+/// Proves the server's answer to a client's PSI query: `differences` are the
+/// blinded differences of `ciphertexts` and the server's `answers`.
+pub async fn post_differences(
+    host: &str,
+    contract_name: &str,
+    prover: &Prover,
+    public_key: &PublicKey,
+    ciphertexts: Ciphertexts,
+    differences: Ciphertexts,
+    answers: Vec<u128>,
+    blindings: Vec<Blinding>,
+    hooks: &impl ActionHooks,
+) -> Result<Submission> {
+    let action = MeetupAction::BlindDifferences {
+        public_key: *public_key,
+        ciphertexts,
+        differences,
     };
+    // The answers and blinding factors never leave the prover.
+    let private_input = borsh::to_vec(&DifferencesInput { answers, blindings })?;
+    submit_action(host, contract_name, prover, action, private_input, hooks).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use contract::StateError;
+    use sdk::ContractInput;
+
+    use crate::simulator::{dev_prover, Simulator};
+    use crate::prover::{DevModeProver, ProverBackend};
+    use crate::psi::{PAILLIER_P, PAILLIER_Q};
+    use crate::settlement::{wait_for_settlement, Settlement};
+    use crypto::paillier::prepare_key;
+
+    const CONTRACT: &str = "meetup";
+
+    /// Proves actions against the contract's initial state, whatever state they're given
+    struct StaleProver;
+
+    #[async_trait::async_trait]
+    impl ProverBackend for StaleProver {
+        async fn prove(&self, mut inputs: ContractInput) -> Result<ProofData> {
+            inputs.state = initial_state().as_bytes()?;
+            DevModeProver.prove(inputs).await
+        }
+
+        fn fakes_receipts(&self) -> bool {
+            true
+        }
+    }
+
+    /// Proves actions as if they were blobs of another transaction
+    struct MisdirectedProver;
+
+    #[async_trait::async_trait]
+    impl ProverBackend for MisdirectedProver {
+        async fn prove(&self, mut inputs: ContractInput) -> Result<ProofData> {
+            inputs.tx_hash = TxHash("another transaction".into());
+            DevModeProver.prove(inputs).await
+        }
+
+        fn fakes_receipts(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn register_post_root_and_post_enc() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        let prover = dev_prover();
+
+        register_contract(host, CONTRACT).await.unwrap();
+        assert!(get_state(host, CONTRACT).await.unwrap().merkle_roots.is_empty());
+
+        let submission = post_root(host, CONTRACT, &prover, "1 9 12 18".into(), &NoHooks).await.unwrap();
+        assert!(node.is_settled(&submission.blob_tx_hash));
+        assert_eq!(node.state(CONTRACT).unwrap().0, submission.expected_state.0);
+        let settlement = wait_for_settlement(host, CONTRACT, &submission, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(settlement, Settlement::Settled);
+        assert_eq!(get_state(host, CONTRACT).await.unwrap().merkle_roots.len(), 1);
+
+        let public_key = PublicKey::Paillier(prepare_key(PAILLIER_P, PAILLIER_Q).0);
+        register_key(host, CONTRACT, &prover, &public_key, &NoHooks).await.unwrap();
+        let submission = post_enc(host, CONTRACT, &prover, &public_key, InterestLayout::Single, "1 9 12 18".into(), &NoHooks)
+            .await
+            .unwrap();
+        assert!(node.is_settled(&submission.blob_tx_hash));
+
+        let state = get_state(host, CONTRACT).await.unwrap();
+        let me = identity(CONTRACT);
+        assert_eq!(state.public_keys.get(&me), Some(&public_key));
+        assert!(state.commitments.contains_key(&me));
+        assert_eq!(state.as_digest().0, submission.expected_state.0);
+    }
+
+    #[tokio::test]
+    async fn rejected_actions_leave_the_state_alone() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        let prover = dev_prover();
+        register_contract(host, CONTRACT).await.unwrap();
+
+        let public_key = PublicKey::Paillier(prepare_key(PAILLIER_P, PAILLIER_Q).0);
+        register_key(host, CONTRACT, &prover, &public_key, &NoHooks).await.unwrap();
+        let before = node.state(CONTRACT).unwrap();
+
+        // A second registration fails when executed locally, so it is never proven
+        assert!(register_key(host, CONTRACT, &prover, &public_key, &NoHooks).await.is_err());
+        // Interests that aren't catalog answers are refused before anything is sent
+        assert!(post_enc(host, CONTRACT, &prover, &public_key, InterestLayout::Single, "3 2".into(), &NoHooks).await.is_err());
+        assert_eq!(node.state(CONTRACT).unwrap().0, before.0);
+
+        // A revoked key isn't registered again by encrypting under it
+        revoke_key(host, CONTRACT, &prover, &NoHooks).await.unwrap();
+        let revoked = node.state(CONTRACT).unwrap();
+        let err = post_enc(host, CONTRACT, &prover, &public_key, InterestLayout::Single, "1 9 12 18".into(), &NoHooks)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("no key registered"));
+        assert_eq!(node.state(CONTRACT).unwrap().0, revoked.0);
+    }
+
+    #[tokio::test]
+    async fn dense_encryptions_pack_several_interests_per_ciphertext() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        let prover = dev_prover();
+        register_contract(host, CONTRACT).await.unwrap();
+
+        let public_key = PublicKey::Paillier(prepare_key(PAILLIER_P, PAILLIER_Q).0);
+        let (action, _) = add_encryption_action(&public_key, InterestLayout::Dense, "1 9 12 18").unwrap();
+        let MeetupAction::AddEncryption { ciphertexts, .. } = action else { unreachable!() };
+        assert!(ciphertexts.len() < 4);
+
+        register_key(host, CONTRACT, &prover, &public_key, &NoHooks).await.unwrap();
+        let submission = post_enc(host, CONTRACT, &prover, &public_key, InterestLayout::Dense, "1 9 12 18".into(), &NoHooks)
+            .await
+            .unwrap();
+        assert!(node.is_settled(&submission.blob_tx_hash));
+        assert!(get_state(host, CONTRACT).await.unwrap().commitments.contains_key(&identity(CONTRACT)));
+    }
+
+    #[tokio::test]
+    async fn elgamal_keys_run_the_same_actions() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        let prover = dev_prover();
+        register_contract(host, CONTRACT).await.unwrap();
+
+        let public_key = crate::keystore::generate_secret_key(contract::SchemeKind::Elgamal).public_key();
+        register_key(host, CONTRACT, &prover, &public_key, &NoHooks).await.unwrap();
+        let submission = post_enc(host, CONTRACT, &prover, &public_key, InterestLayout::Single, "1 9 12 18".into(), &NoHooks)
+            .await
+            .unwrap();
+        assert!(node.is_settled(&submission.blob_tx_hash));
+        let state = get_state(host, CONTRACT).await.unwrap();
+        assert_eq!(state.public_keys.get(&identity(CONTRACT)), Some(&public_key));
+
+        // Ciphertexts of another scheme are refused under the ElGamal key
+        let paillier_key = PublicKey::Paillier(prepare_key(PAILLIER_P, PAILLIER_Q).0);
+        let (mut action, private_input) = add_encryption_action(&paillier_key, InterestLayout::Single, "1 9 12 18").unwrap();
+        if let MeetupAction::AddEncryption { public_key: key, .. } = &mut action {
+            *key = public_key;
+        }
+        assert!(submit_action(host, CONTRACT, &prover, action, private_input, &NoHooks).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn batched_actions_are_proven_on_each_others_state() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        let prover = dev_prover();
+        register_contract(host, CONTRACT).await.unwrap();
+
+        let public_key = PublicKey::Paillier(prepare_key(PAILLIER_P, PAILLIER_Q).0);
+        let actions = vec![
+            (MeetupAction::PostRoot, b"1 9 12 18".to_vec()),
+            (MeetupAction::RegisterPublicKey { public_key }, vec![]),
+            add_encryption_action(&public_key, InterestLayout::Single, "1 9 12 18").unwrap(),
+        ];
+        let submission = submit_batch(host, CONTRACT, &prover, actions, &NoHooks).await.unwrap();
+        assert_eq!(submission.proofs.len(), 3);
+        let me = identity(CONTRACT);
+        assert!(matches!(&submission.events[0], MeetupEvent::RootPosted { index: 0, .. }));
+        assert_eq!(submission.events[1], MeetupEvent::KeyRegistered { identity: me.clone() });
+        assert!(matches!(&submission.events[2], MeetupEvent::EncryptionCommitted { identity, .. } if *identity == me));
+        assert!(node.is_settled(&submission.blob_tx_hash));
+
+        let state = get_state(host, CONTRACT).await.unwrap();
+        assert_eq!(state.merkle_roots.len(), 1);
+        assert!(state.commitments.contains_key(&me));
+        assert_eq!(state.as_digest().0, submission.expected_state.0);
+
+        // The key of the last action isn't registered before the batch, nor by it
+        let other_key = PublicKey::Paillier(prepare_key(7759, 6983).0);
+        let actions = vec![
+            (MeetupAction::PostRoot, b"2 9 12 18".to_vec()),
+            add_encryption_action(&other_key, InterestLayout::Single, "1 9 12 18").unwrap(),
+        ];
+        assert!(submit_batch(host, CONTRACT, &prover, actions, &NoHooks).await.is_err());
+        assert_eq!(node.state(CONTRACT).unwrap().0, submission.expected_state.0);
+    }
+
+    #[tokio::test]
+    async fn aggregated_proofs_settle_in_one_transaction() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        let prover = dev_prover();
+        register_contract(host, CONTRACT).await.unwrap();
+        let config = aggregate::AggregationConfig {
+            max_proofs: 2,
+            max_wait: Duration::from_millis(200),
+        };
+        aggregate::enable(host, config).await.unwrap();
+        // Registered by `enable`, which accepts it from then on
+        aggregate::enable(host, config).await.unwrap();
+
+        let public_key = PublicKey::Paillier(prepare_key(PAILLIER_P, PAILLIER_Q).0);
+        let actions = vec![
+            (MeetupAction::PostRoot, b"1 9 12 18".to_vec()),
+            (MeetupAction::RegisterPublicKey { public_key }, vec![]),
+        ];
+        let batch = submit_batch(host, CONTRACT, &prover, actions, &NoHooks).await.unwrap();
+        assert_eq!(batch.proof_tx_hashes[0], batch.proof_tx_hashes[1]);
+        assert!(node.is_settled(&batch.blob_tx_hash));
+
+        // Alone in the queue, a proof goes out once the wait is over
+        let single = post_root(host, CONTRACT, &prover, "2 9 12 18".into(), &NoHooks).await.unwrap();
+        assert!(node.is_settled(&single.blob_tx_hash));
+        assert_eq!(node.state(CONTRACT).unwrap().0, single.expected_state.0);
+
+        let covered: Vec<_> = aggregate::records()
+            .into_iter()
+            .filter(|record| record.host == host)
+            .map(|record| (record.blob_tx_hashes, record.proofs))
+            .collect();
+        assert_eq!(
+            covered,
+            [(vec![batch.blob_tx_hash.0.clone()], 2), (vec![single.blob_tx_hash.0.clone()], 1)]
+        );
+
+        // A node running another program under the aggregate contract's name is refused
+        let other = Simulator::for_tests().unwrap();
+        let registration = sdk::api::APIRegisterContract {
+            verifier: "risc0".into(),
+            program_id: ProgramId(sdk::to_u8_array(&GUEST_ID).to_vec()),
+            state_digest: StateDigest(Vec::new()),
+            contract_name: aggregate::AGGREGATE_CONTRACT.into(),
+        };
+        let client = NodeApiHttpClient::new(other.url().to_string()).unwrap();
+        client.register_contract(&registration).await.unwrap();
+        assert!(aggregate::enable(other.url(), config).await.is_err());
+        assert!(!aggregate::enabled(other.url()));
+    }
+
+    #[tokio::test]
+    async fn legacy_states_are_migrated_before_use() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        let prover = dev_prover();
+
+        // Registered before the state envelope existed: the bare borsh encoding
+        // of the original two fields
+        let legacy = StateDigest(borsh::to_vec(&(vec![7u128], String::new())).unwrap());
+        assert_eq!(Meetup::try_from(legacy.clone()).unwrap().as_digest(), legacy);
+        let client = NodeApiHttpClient::new(host.to_string()).unwrap();
+        let registration = sdk::api::APIRegisterContract {
+            verifier: "risc0".into(),
+            program_id: ProgramId(sdk::to_u8_array(&GUEST_ID).to_vec()),
+            state_digest: legacy,
+            contract_name: CONTRACT.into(),
+        };
+        client.register_contract(&registration).await.unwrap();
+        assert_eq!(get_state(host, CONTRACT).await.unwrap().version, contract::state::LEGACY_STATE_VERSION);
+
+        let err = post_root(host, CONTRACT, &prover, "1 9 12 18".into(), &NoHooks).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("send Migrate"));
+
+        let submission = migrate_state(host, CONTRACT, &prover, &NoHooks).await.unwrap();
+        assert!(node.is_settled(&submission.blob_tx_hash));
+        let state = get_state(host, CONTRACT).await.unwrap();
+        assert_eq!((state.version, state.merkle_roots), (contract::STATE_VERSION, vec![7]));
+        assert!(migrate_state(host, CONTRACT, &prover, &NoHooks).await.is_err());
+        post_root(host, CONTRACT, &prover, "1 9 12 18".into(), &NoHooks).await.unwrap();
+
+        // Layouts from the future and garbage are errors, not panics
+        let mut future = contract::state::STATE_MAGIC.to_vec();
+        future.extend(99u16.to_le_bytes());
+        assert_eq!(Meetup::try_from(StateDigest(future)).unwrap_err(), StateError::UnknownVersion(99));
+        assert!(matches!(Meetup::try_from(StateDigest(vec![1, 2, 3])), Err(StateError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn proofs_of_another_transition_are_not_submitted() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        register_contract(host, CONTRACT).await.unwrap();
+        post_root(host, CONTRACT, &dev_prover(), "1 9 12 18".into(), &NoHooks).await.unwrap();
+        let before = node.state(CONTRACT).unwrap();
+
+        let stale: Prover = std::sync::Arc::new(StaleProver);
+        let err = post_root(host, CONTRACT, &stale, "2 9 12 18".into(), &NoHooks).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("starts from another state"));
+        assert_eq!(node.state(CONTRACT).unwrap().0, before.0);
+    }
 
-    let proof_tx_hash = client.send_tx_proof(&proof_tx).await?;
-    println!("✅ Proof tx sent. Tx hash: {}", proof_tx_hash);
+    #[tokio::test]
+    async fn proofs_of_another_transaction_are_not_sent() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        register_contract(host, CONTRACT).await.unwrap();
+        let before = node.state(CONTRACT).unwrap();
 
-    Ok(proof_tx_hash.to_string())
+        let misdirected: Prover = std::sync::Arc::new(MisdirectedProver);
+        let err = post_root(host, CONTRACT, &misdirected, "1 9 12 18".into(), &NoHooks).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("another transaction"));
+        assert_eq!(node.state(CONTRACT).unwrap().0, before.0);
+    }
 }
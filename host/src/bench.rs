@@ -0,0 +1,106 @@
+// host/src/bench.rs
+
+use anyhow::Result;
+use contract::{interest_packing, Ciphertexts, EncryptionInput, InterestLayout, Meetup, MeetupAction, PublicKey};
+use crypto::{HomomorphicScheme, Paillier};
+use methods::{GUEST_ELF, GUEST_NAIVE_ELF};
+use risc0_zkvm::{default_executor, ExecutorEnv};
+use sdk::{BlobIndex, ContractInput};
+
+use crate::psi::{sample_units, PAILLIER_P, PAILLIER_Q};
+
+/// Guest cycles of the same `AddEncryption` under each `mod_exp`.
+pub struct AddEncryptionCycles {
+    /// Plain square-and-multiply (`methods/guest-naive`)
+    pub naive: u64,
+    /// Montgomery / fixed-window on risc0's bigint accelerator (`methods/guest`)
+    pub montgomery: u64,
+}
+
+/// Runs an `AddEncryption` of `interests` through both guests' executors (no
+/// proving), on the same inputs, and returns the number of cycles each took.
+pub fn add_encryption_cycles(interests: &str) -> Result<AddEncryptionCycles> {
+    let inputs = add_encryption_inputs(interests)?;
+    Ok(AddEncryptionCycles {
+        naive: cycles(&inputs, GUEST_NAIVE_ELF)?,
+        montgomery: cycles(&inputs, GUEST_ELF)?,
+    })
+}
+
+fn cycles(inputs: &ContractInput, elf: &[u8]) -> Result<u64> {
+    let env = ExecutorEnv::builder().write(inputs)?.build()?;
+    let session = default_executor().execute(env, elf)?;
+    Ok(session.cycles())
+}
+
+/// Guest input of an `AddEncryption` of `interests` on a fresh state holding only its key.
+fn add_encryption_inputs(interests: &str) -> Result<ContractInput> {
+    let (public_key, _) = Paillier::keygen(&(PAILLIER_P, PAILLIER_Q));
+    let messages = interests
+        .split_whitespace()
+        .map(|x| x.parse::<u128>())
+        .collect::<Result<Vec<u128>, _>>()?;
+    let packing = interest_packing();
+    let nonces = sample_units::<Paillier>(&public_key, packing.plaintexts_for(messages.len()));
+    let ciphertexts = packing.encrypt::<Paillier>(&public_key, &messages, &nonces);
+
+    let action = MeetupAction::AddEncryption {
+        public_key: PublicKey::Paillier(public_key),
+        ciphertexts: Ciphertexts::Paillier(ciphertexts),
+        layout: InterestLayout::Single,
+    };
+    let identity = "bench.meetup".to_string();
+    let mut initial_state = Meetup::new();
+    initial_state.public_keys.insert(identity.clone(), PublicKey::Paillier(public_key));
+    let inputs = ContractInput {
+        state: initial_state.as_bytes()?,
+        identity: sdk::Identity(identity),
+        tx_hash: sdk::TxHash("bench".to_string()),
+        private_input: borsh::to_vec(&EncryptionInput {
+            interests: messages,
+            nonces,
+        })?,
+        tx_ctx: None,
+        blobs: vec![action.as_blob("meetup")],
+        index: BlobIndex(0),
+    };
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdk::HyleOutput;
+
+    fn execute(inputs: &ContractInput, elf: &[u8]) -> HyleOutput {
+        let env = ExecutorEnv::builder().write(inputs).unwrap().build().unwrap();
+        let session = default_executor().execute(env, elf).unwrap();
+        session.journal.decode().unwrap()
+    }
+
+    /// The guest runs `mod_exp` on risc0's bigint accelerator (`zkvm-accel`), so
+    /// it only accepts the host's Montgomery-made ciphertexts if both agree.
+    #[test]
+    fn guest_mod_exp_agrees_with_the_host() {
+        let inputs = add_encryption_inputs("1 9 12 18").unwrap();
+        assert!(execute(&inputs, GUEST_ELF).success);
+
+        let mut input: EncryptionInput = borsh::from_slice(&inputs.private_input).unwrap();
+        input.nonces.swap(0, 1);
+        let swapped = ContractInput {
+            private_input: borsh::to_vec(&input).unwrap(),
+            ..inputs
+        };
+        assert!(!execute(&swapped, GUEST_ELF).success);
+    }
+
+    /// The baseline only swaps `mod_exp`, so it must accept the same inputs for
+    /// the cycle counts to be comparable.
+    #[test]
+    fn both_guests_accept_the_benchmark_inputs() {
+        let inputs = add_encryption_inputs("1 9 12 18").unwrap();
+        let (naive, montgomery) = (execute(&inputs, GUEST_NAIVE_ELF), execute(&inputs, GUEST_ELF));
+        assert!(naive.success && montgomery.success);
+        assert_eq!(naive.next_state, montgomery.next_state);
+    }
+}
@@ -1,19 +1,45 @@
 // host/src/http_server.rs
 
-use actix_web::{post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::collections::HashMap;
+use std::sync::Arc;
+use crate::aggregate;
 use crate::api;
+use crate::indexer::db::IndexDb;
+use crate::jobs::{CancelError, JobId, JobQueue};
+use crate::keystore::{Keystore, SecretKey};
+use crate::prover::{Prover, ProverBackend};
+use crate::settlement::wait_for_settlement;
+use crate::psi::{self, sample_blindings, server_code_batch};
+use crate::proof::verify_answers_proof;
+use contract::{catalog, with_scheme, Ciphertexts, InterestLayout, PublicKey, TaggedScheme};
 use awc::Client;
 use actix_cors::Cors;
 use actix_web::{middleware};
-use sdk::{BlobIndex, ContractInput};
+use sdk::{BlobIndex, ContractInput, ProofData};
 use contract::{Meetup, MeetupAction};
 use methods::GUEST_ELF;
 
-const HYLE_BLOCKCHAIN_SERVER: &str = "http://localhost:4321";
-const HYLE_BLOCKCHAIN_URL: &str = "http://localhost:4321/v1";
+pub const CONTRACT_NAME: &str = "test4";
+
+/// The server's keystore, unlocked once at startup.
+pub struct ServerKeys {
+    pub keystore: Keystore,
+    pub passphrase: String,
+    /// The `--key` entry every PSI runs under; requests can't pick another one
+    pub default_key: String,
+}
+
+/// URL of the node the server registers, proves and settles against (the CLI's `--host`)
+pub struct NodeUrl(pub String);
+
+impl ServerKeys {
+    fn load(&self) -> anyhow::Result<(PublicKey, SecretKey)> {
+        self.keystore.load(&self.default_key, &self.passphrase)
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct RegisterContractRequest {
@@ -22,7 +48,6 @@ struct RegisterContractRequest {
 
 #[derive(Debug, Deserialize)]
 struct PostRootRequest {
-    host: String,
     contract_name: String,
     answers: Vec<AnsweredQuestions>,
 }
@@ -37,14 +62,26 @@ struct AnsweredQuestions {
 struct InterestsRequest {
     meetCode: String,
     address: String,
+    #[serde(default)]
     answers: Vec<AnsweredQuestions>,
+    /// Set when the client encrypted its answers itself (one per ciphertext, see
+    /// `contract::interest_packing`), under a key of any scheme: the server then
+    /// only returns the blinded differences for the client to decrypt.
+    public_key: Option<PublicKey>,
+    /// Ciphertexts of the key's scheme, required along with it
+    ciphertexts: Option<Ciphertexts>,
+    /// Hex encoded proof of the client's `AddEncryption` of `ciphertexts`,
+    /// required along with them (see `proof::verify_answers_proof`).
+    proof: Option<String>,
+    /// Identity the client sent that `AddEncryption` as, required along with the proof
+    identity: Option<String>,
 }
 
 #[post("/register-contract")]
-async fn register_contract(req: web::Json<RegisterContractRequest>) -> impl Responder {
+async fn register_contract(req: web::Json<RegisterContractRequest>, node: web::Data<NodeUrl>) -> impl Responder {
     println!("Received data: {:?}", req);
     
-    match api::register_contract(HYLE_BLOCKCHAIN_SERVER, &req.contract_name).await {
+    match api::register_contract(&node.0, &req.contract_name).await {
         Ok(tx_hash) => {
             // Return original tx_hash regardless of POST result
             HttpResponse::Ok().json(serde_json::json!({ "tx_hash": tx_hash }))
@@ -53,12 +90,31 @@ async fn register_contract(req: web::Json<RegisterContractRequest>) -> impl Resp
     }
 }
 
+/// Answers with the queued job's ID merged into `body`, or 503 if the queue is full.
+fn job_response(submitted: Result<JobId, crate::jobs::SubmitError>, mut body: serde_json::Value) -> HttpResponse {
+    match submitted {
+        Ok(job_id) => {
+            body["job_id"] = serde_json::json!(job_id);
+            HttpResponse::Accepted().json(body)
+        }
+        Err(err) => HttpResponse::ServiceUnavailable().body(err.to_string()),
+    }
+}
+
 #[post("/post-root")]
-async fn post_root(req: web::Json<PostRootRequest>) -> impl Responder {
+async fn post_root(
+    req: web::Json<PostRootRequest>,
+    jobs: web::Data<JobQueue>,
+    prover: web::Data<dyn ProverBackend>,
+    node: web::Data<NodeUrl>,
+) -> impl Responder {
     println!("Received root data: {:?}", req);
 
-    let alice_interests_vec: Vec<u128> = req.answers.iter().map(
-        |x| x.id * 5 + x.answerId
+    // Answers are compared question by question
+    let mut alice_answers = req.answers.clone();
+    alice_answers.sort_by_key(|x| x.id);
+    let alice_interests_vec: Vec<u128> = alice_answers.iter().map(
+        |x| catalog::encode_answer(x.id, x.answerId)
     ).collect();
     let alice_interests_string = alice_interests_vec.iter().map(
         |x| x.to_string()
@@ -72,14 +128,46 @@ async fn post_root(req: web::Json<PostRootRequest>) -> impl Responder {
         AnsweredQuestions{ id: 3, answerId: 3 },
     ];
     
-    match api::post_root(&req.host, &req.contract_name, alice_interests_string).await {
-        Ok(tx_hash) => HttpResponse::Ok().json(serde_json::json!({ "tx_hash": tx_hash })),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    let (host, contract_name, prover) = (node.0.clone(), req.contract_name.clone(), prover.into_inner());
+    let timeout = jobs.settle_timeout();
+    let submitted = jobs.submit("post_root", move |progress| async move {
+        let submission = api::post_root(&host, &contract_name, &prover, alice_interests_string, &progress).await?;
+        wait_for_settlement(&host, &contract_name, &submission, timeout).await
+    });
+    job_response(submitted, serde_json::json!({}))
+}
+
+/// The proof aggregates sent so far and the blob transactions each covers
+#[get("/aggregates")]
+async fn aggregates() -> impl Responder {
+    HttpResponse::Ok().json(aggregate::records())
+}
+
+#[get("/jobs/{id}")]
+async fn job_status(id: web::Path<JobId>, jobs: web::Data<JobQueue>) -> impl Responder {
+    match jobs.status(*id) {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().body(format!("No job {}", id)),
+    }
+}
+
+#[post("/jobs/{id}/cancel")]
+async fn cancel_job(id: web::Path<JobId>, jobs: web::Data<JobQueue>) -> impl Responder {
+    match jobs.cancel(*id) {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(CancelError::NotFound) => HttpResponse::NotFound().body(format!("No job {}", id)),
+        Err(CancelError::AlreadyFinished(state)) => HttpResponse::Conflict().json(state),
     }
 }
 
 #[post("/receive-interests")]
-async fn receive_interests(req: web::Json<InterestsRequest>) -> impl Responder {
+async fn receive_interests(
+    req: web::Json<InterestsRequest>,
+    keys: web::Data<ServerKeys>,
+    jobs: web::Data<JobQueue>,
+    prover: web::Data<dyn ProverBackend>,
+    node: web::Data<NodeUrl>,
+) -> impl Responder {
     println!("Received interests: {:?}", req);
 
     let BOB_INTERESTS = vec![
@@ -91,23 +179,84 @@ async fn receive_interests(req: web::Json<InterestsRequest>) -> impl Responder {
 
 
     let bob_interests_vec: Vec<u128> = BOB_INTERESTS.iter().map(
-        |x| x.id * 5 + x.answerId
+        |x| catalog::encode_answer(x.id, x.answerId)
     ).collect();
+
+    if let Some(public_key) = &req.public_key {
+        let Some(ciphertexts) = &req.ciphertexts else {
+            return HttpResponse::BadRequest().body("A public key needs the ciphertexts encrypted under it");
+        };
+        if ciphertexts.kind() != public_key.kind() {
+            return HttpResponse::BadRequest().body(format!("Ciphertexts are not {} ciphertexts", public_key.kind()));
+        }
+        let Some(proof) = req.proof.as_ref().and_then(|p| hex::decode(p).ok()) else {
+            return HttpResponse::BadRequest().body("Client-side ciphertexts need a hex encoded AddEncryption proof");
+        };
+        let Some(identity) = &req.identity else {
+            return HttpResponse::BadRequest().body("Client-side ciphertexts need the identity their proof was sent as");
+        };
+
+        // Ciphertexts under a rotated or revoked key are stale: the client must re-encrypt.
+        let state = match api::get_state(&node.0, CONTRACT_NAME).await {
+            Ok(state) if !state.is_committed(public_key, ciphertexts) => {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "Ciphertexts are not committed under a current key; re-encrypt and post them again",
+                    "reencrypt": true,
+                }));
+            }
+            Ok(state) => state,
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        };
+        if let Err(err) = verify_answers_proof(&ProofData(proof), CONTRACT_NAME, identity, public_key, ciphertexts, &state) {
+            return HttpResponse::Forbidden().body(format!("Invalid answers proof: {:#}", err));
+        }
+        if ciphertexts.len() != bob_interests_vec.len() {
+            return HttpResponse::BadRequest().body(format!(
+                "Expected {} ciphertexts, got {}",
+                bob_interests_vec.len(),
+                ciphertexts.len()
+            ));
+        }
+        let (blindings, differences) = with_scheme!(public_key.kind(), S => {
+            let pk = S::key(public_key).expect("the key is of its own scheme");
+            let cs = S::ciphertexts(ciphertexts).expect("checked to be of the key's scheme");
+            let blindings = sample_blindings::<S>(pk, bob_interests_vec.len());
+            let differences = server_code_batch::<S>(&bob_interests_vec, cs, pk, &blindings);
+            (blindings, S::tag_ciphertexts(differences))
+        });
+        // Prove the differences were honestly blinded, without revealing our answers.
+        // The client only gets them from the job once that proof settles.
+        let result = serde_json::json!({ "differences": differences });
+        let (public_key, ciphertexts, proven) = (*public_key, ciphertexts.clone(), differences);
+        let (host, prover) = (node.0.clone(), prover.into_inner());
+        let timeout = jobs.settle_timeout();
+        let submitted = jobs.submit_with_result("blind_differences", Some(result), move |progress| async move {
+            let submission = api::post_differences(
+                &host,
+                CONTRACT_NAME,
+                &prover,
+                &public_key,
+                ciphertexts,
+                proven,
+                bob_interests_vec,
+                blindings,
+                &progress,
+            ).await?;
+            wait_for_settlement(&host, CONTRACT_NAME, &submission, timeout).await
+        });
+        return job_response(submitted, serde_json::json!({}));
+    }
     let alice_interests_vec: Vec<u128> = req.answers.iter().map(
-        |x| x.id * 5 + x.answerId
+        |x| catalog::encode_answer(x.id, x.answerId)
     ).collect();
     let alice_interests_string = alice_interests_vec.iter().map(
         |x| x.to_string()
     ).collect::<Vec<String>>().join(" ");
 
-    let p = 7759;
-    let q = 6983;
-    let (pk, sk) = prepare_key(p, q);
-    let alice_interests_vec_enc = alice_interests_vec.iter().map(
-        |x| encrypt(*x, pk)
-    ).collect();
-
-    let result = server_code_batch(bob_interests_vec, alice_interests_vec_enc, pk);
+    let (pk, sk) = match keys.load() {
+        Ok(keys) => keys,
+        Err(err) => return HttpResponse::InternalServerError().body(format!("Could not load the server key: {:#}", err)),
+    };
 
     /*
     // Save back to file
@@ -127,129 +276,107 @@ async fn receive_interests(req: web::Json<InterestsRequest>) -> impl Responder {
     }
     */
 
-    let intersection_numbers = client_find_intersection(result, sk);
+    if alice_interests_vec.len() != bob_interests_vec.len() {
+        return HttpResponse::BadRequest().body(format!("Expected {} answers", bob_interests_vec.len()));
+    }
+    if !catalog::are_valid_answers(&alice_interests_vec) {
+        return HttpResponse::BadRequest().body("Answers must cover the first questions of the catalog, one answer each");
+    }
+    let intersection_numbers = match psi::find_intersection(&pk, &sk, &alice_interests_vec, bob_interests_vec) {
+        Ok(intersection_numbers) => intersection_numbers,
+        Err(err) => return HttpResponse::InternalServerError().body(format!("{:#}", err)),
+    };
     let mut intersection = Vec::new();
     for i in 0..intersection_numbers.len() {
         if intersection_numbers[i] {
             intersection.push(BOB_INTERESTS[i].clone());
         }
     }
-    match api::post_enc(
-        HYLE_BLOCKCHAIN_SERVER,
-        "test4",
-        p,
-        q,
-        alice_interests_string,
-    ).await {
-        Ok(tx_hash) =>
-            HttpResponse::Ok().json(serde_json::json!({
-            "intersection": intersection,
-            "tx_hash": tx_hash,
-        })),
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
-    }
-}
-
-// pub async fn run_server() -> std::io::Result<()> {
-//     println!("Starting HTTP server on 127.0.0.1:8080");
-//     HttpServer::new(|| {
-//         App::new()
-//             .service(register_contract)
-//             .service(post_root)
-//             .service(receive_hashed_interests)
-//     })
-//     .bind(("127.0.0.1", 8080))?
-//     .run()
-//     .await
-// }
-fn server_code_batch(y_secret: Vec<u128>, c_x: Vec<u128>, pk: [u128; 2]) -> Vec<u128> {
-    let mut result : Vec<u128> = Vec::new();
-    assert!(y_secret.len() == c_x.len());
-    let m = c_x.len();
-    for i in 0..m {
-        let c_y = encrypt(y_secret[i], pk);
-        let c_y_inv = invert_cipher(c_y, pk[0]);
-        result.push((c_x[i] * c_y_inv) % (pk[0] * pk[0]));
-    }
-    result
-}
-
-fn invert_cipher(c: u128, n: u128) -> u128 {
-    let n_sq = n * n;
-    mod_exp(c, n - 1, n_sq)
-}
-
-fn l_function(x: u128, n: u128) -> u128 {
-    (x - 1) / n
+    let (timeout, host, prover) = (jobs.settle_timeout(), node.0.clone(), prover.into_inner());
+    let submitted = jobs.submit("add_encryption", move |progress| async move {
+        // AddEncryption needs a registered key: the server registers its own in the same transaction
+        let mut actions = Vec::new();
+        if !api::get_state(&host, CONTRACT_NAME).await?.public_keys.contains_key(&api::identity(CONTRACT_NAME)) {
+            actions.push((MeetupAction::RegisterPublicKey { public_key: pk }, vec![]));
+        }
+        // Nobody blinds differences against the server's own commitment, so it can be dense
+        actions.push(api::add_encryption_action(&pk, InterestLayout::Dense, &alice_interests_string)?);
+        let submission = api::submit_batch(&host, CONTRACT_NAME, &prover, actions, &progress).await?;
+        wait_for_settlement(&host, CONTRACT_NAME, &submission, timeout).await
+    });
+    job_response(submitted, serde_json::json!({
+        "intersection": intersection,
+        "public_key": pk,
+    }))
 }
 
-fn mod_inv(a: u128, m: u128) -> u128 {
-    let (g, x, _) = extended_gcd(a as i128, m as i128);
-    if g == 1 {
-        ((x % m as i128 + m as i128) % m as i128) as u128
-    } else {
-        panic!("No modular inverse exists!");
-    }
+#[derive(Debug, Deserialize)]
+struct SessionsQuery {
+    /// Only the sessions answering these ciphertexts (see `Meetup::commitment`)
+    commitment: Option<String>,
 }
 
-fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
-    if a == 0 {
-        return (b, 0, 1);
+/// Answers with `records` as JSON, or 500 if the index could not be read.
+fn history_response<T: Serialize>(records: anyhow::Result<Vec<T>>) -> HttpResponse {
+    match records {
+        Ok(records) => HttpResponse::Ok().json(records),
+        Err(err) => HttpResponse::InternalServerError().body(format!("{:#}", err)),
     }
-    let (g, x1, y1) = extended_gcd(b % a, a);
-    let x = y1 - (b / a) * x1;
-    let y = x1;
-    (g, x, y)
 }
 
-fn mod_exp(mut base: u128, mut exp: u128, modulus: u128) -> u128 {
-    let mut result = 1;
-    base = base % modulus;
-    while exp > 0 {
-        if exp % 2 == 1 {
-            result = (result * base) % modulus;
-        }
-        exp = exp >> 1;
-        base = (base * base) % modulus;
-    }
-    result
+#[get("/history/roots/{identity}")]
+async fn history_roots(identity: web::Path<String>, db: web::Data<IndexDb>) -> impl Responder {
+    history_response(db.roots(&identity))
 }
 
-// paillier
-fn prepare_key (p: u128, q: u128) -> ([u128; 2], [u128; 3]) {
-    let n = p * q;
-    let lambda = num_integer::lcm(p - 1, q - 1);
-    let g = n + 1; // Standard choice for g
-    let mu = mod_inv(l_function(mod_exp(g, lambda, n * n), n), n);
-
-    ([n, g], [n, lambda, mu])
+#[get("/history/keys/{identity}")]
+async fn history_keys(identity: web::Path<String>, db: web::Data<IndexDb>) -> impl Responder {
+    history_response(db.key_events(&identity))
 }
 
-fn encrypt(m: u128, pk: [u128; 2]) -> u128 {
-    let [n, g] = pk;
-    let n_sq = n * n;
-    let r = 3; // Fixed r for simplicity (should be random < n)
-    (mod_exp(g, m, n_sq) * mod_exp(r, n, n_sq)) % n_sq
+#[get("/history/commitments/{identity}")]
+async fn history_commitments(identity: web::Path<String>, db: web::Data<IndexDb>) -> impl Responder {
+    history_response(db.commitments(&identity))
 }
 
-fn decrypt(c: u128, sk: [u128; 3]) -> u128 {
-    let [n, lambda, mu] = sk;
-    let n_sq = n * n;
-    let l_value = l_function(mod_exp(c, lambda, n_sq), n);
-    (l_value * mu) % n
+#[get("/history/events/{identity}")]
+async fn history_events(identity: web::Path<String>, db: web::Data<IndexDb>) -> impl Responder {
+    history_response(db.events(&identity))
 }
 
-fn client_find_intersection(c_y: Vec<u128>, sk: [u128; 3]) -> Vec<bool> {
-    let mut result : Vec<bool> = Vec::new();
-    for c in c_y{
-        result.push(decrypt(c, sk) == 0);
-    }
-    result
+#[get("/history/sessions")]
+async fn history_sessions(query: web::Query<SessionsQuery>, db: web::Data<IndexDb>) -> impl Responder {
+    history_response(db.sessions(query.commitment.as_deref()))
 }
 
-pub async fn run_server() -> std::io::Result<()> {
-    println!("Starting HTTP server on 127.0.0.1:8080");
-    HttpServer::new(|| {
+// pub async fn run_server() -> std::io::Result<()> {
+//     println!("Starting HTTP server on 127.0.0.1:8080");
+//     HttpServer::new(|| {
+//         App::new()
+//             .service(register_contract)
+//             .service(post_root)
+//             .service(receive_hashed_interests)
+//     })
+//     .bind(("127.0.0.1", 8080))?
+//     .run()
+//     .await
+// }
+/// Serves the PSI endpoints against the node at `host`, plus the `/history` ones
+/// when given the indexer's `history`.
+pub async fn run_server(
+    host: String,
+    keys: ServerKeys,
+    jobs: JobQueue,
+    prover: Prover,
+    history: Option<Arc<IndexDb>>,
+) -> std::io::Result<()> {
+    println!("Starting HTTP server on 127.0.0.1:8080 (node at {})", host);
+    let node = web::Data::new(NodeUrl(host));
+    let keys = web::Data::new(keys);
+    let jobs = web::Data::new(jobs);
+    let prover = web::Data::from(prover);
+    let history = history.map(web::Data::from);
+    HttpServer::new(move || {
         // Configure CORS middleware
         let cors = Cors::default()
             .allow_any_origin() // Change to your allowed domain
@@ -263,9 +390,26 @@ pub async fn run_server() -> std::io::Result<()> {
         App::new()
             .wrap(cors)
             .wrap(middleware::Logger::default())
+            .app_data(keys.clone())
+            .app_data(jobs.clone())
+            .app_data(prover.clone())
+            .app_data(node.clone())
             .service(register_contract)
             .service(post_root)
             .service(receive_interests)
+            .service(job_status)
+            .service(cancel_job)
+            .service(aggregates)
+            .configure(|cfg| {
+                if let Some(history) = &history {
+                    cfg.app_data(history.clone())
+                        .service(history_roots)
+                        .service(history_keys)
+                        .service(history_commitments)
+                        .service(history_events)
+                        .service(history_sessions);
+                }
+            })
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -0,0 +1,265 @@
+// host/src/indexer/db.rs
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use contract::MeetupEvent;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value BLOB NOT NULL);
+CREATE TABLE IF NOT EXISTS roots (
+    height INTEGER NOT NULL, tx_hash TEXT NOT NULL, identity TEXT NOT NULL, root TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS commitments (
+    height INTEGER NOT NULL, tx_hash TEXT NOT NULL, identity TEXT NOT NULL,
+    public_key TEXT NOT NULL, commitment TEXT NOT NULL, ciphertexts INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS key_events (
+    height INTEGER NOT NULL, tx_hash TEXT NOT NULL, identity TEXT NOT NULL,
+    kind TEXT NOT NULL, public_key TEXT
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    height INTEGER NOT NULL, tx_hash TEXT NOT NULL, server TEXT NOT NULL,
+    query_commitment TEXT NOT NULL, differences INTEGER NOT NULL, differences_hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS events (
+    height INTEGER NOT NULL, tx_hash TEXT NOT NULL, identity TEXT NOT NULL, event TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS roots_identity ON roots (identity);
+CREATE INDEX IF NOT EXISTS commitments_identity ON commitments (identity);
+CREATE INDEX IF NOT EXISTS key_events_identity ON key_events (identity);
+CREATE INDEX IF NOT EXISTS events_identity ON events (identity);
+";
+
+/// A Merkle root posted with `PostRoot`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RootRecord {
+    pub height: u64,
+    pub tx_hash: String,
+    pub identity: String,
+    /// Decimal, as it doesn't fit an SQLite integer
+    pub root: String,
+}
+
+/// Ciphertexts committed with `AddEncryption`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitmentRecord {
+    pub height: u64,
+    pub tx_hash: String,
+    pub identity: String,
+    /// Hex encoded
+    pub public_key: String,
+    /// See `Meetup::commitment`
+    pub commitment: String,
+    pub ciphertexts: usize,
+}
+
+/// A key registration, rotation or revocation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyEvent {
+    pub height: u64,
+    pub tx_hash: String,
+    pub identity: String,
+    /// "register", "rotate" or "revoke"
+    pub kind: String,
+    /// The key in force afterwards, hex encoded; none after a revocation
+    pub public_key: Option<String>,
+}
+
+/// A PSI answer proven with `BlindDifferences`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionRecord {
+    pub height: u64,
+    pub tx_hash: String,
+    /// Identity of the answering server
+    pub server: String,
+    /// Commitment of the client ciphertexts the session answered
+    pub query_commitment: String,
+    pub differences: usize,
+    pub differences_hash: String,
+}
+
+/// A `MeetupEvent` a settled blob reported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EventRecord {
+    pub height: u64,
+    pub tx_hash: String,
+    pub event: MeetupEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Root(RootRecord),
+    Commitment(CommitmentRecord),
+    Key(KeyEvent),
+    Session(SessionRecord),
+    Event(EventRecord),
+}
+
+/// SQLite store of the indexed history.
+pub struct IndexDb {
+    conn: Mutex<Connection>,
+}
+
+impl IndexDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(IndexDb {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Last block whose records are stored
+    pub fn last_height(&self) -> Result<Option<u64>> {
+        let conn = self.conn.lock().unwrap();
+        let height: Option<i64> = conn
+            .query_row("SELECT value FROM meta WHERE key = 'last_height'", [], |row| row.get(0))
+            .optional()?;
+        Ok(height.map(|h| h as u64))
+    }
+
+    /// Borsh encoded contract state after the last stored block
+    pub fn state(&self) -> Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row("SELECT value FROM meta WHERE key = 'state'", [], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Stores the records of block `height` and advances the cursor, atomically.
+    pub fn store_block(&self, height: u64, records: &[Record], state: Option<&[u8]>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for record in records {
+            match record {
+                Record::Root(r) => tx.execute(
+                    "INSERT INTO roots VALUES (?1, ?2, ?3, ?4)",
+                    params![r.height as i64, r.tx_hash, r.identity, r.root],
+                )?,
+                Record::Commitment(c) => tx.execute(
+                    "INSERT INTO commitments VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![c.height as i64, c.tx_hash, c.identity, c.public_key, c.commitment, c.ciphertexts as i64],
+                )?,
+                Record::Key(k) => tx.execute(
+                    "INSERT INTO key_events VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![k.height as i64, k.tx_hash, k.identity, k.kind, k.public_key],
+                )?,
+                Record::Session(s) => tx.execute(
+                    "INSERT INTO sessions VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        s.height as i64,
+                        s.tx_hash,
+                        s.server,
+                        s.query_commitment,
+                        s.differences as i64,
+                        s.differences_hash
+                    ],
+                )?,
+                Record::Event(e) => tx.execute(
+                    "INSERT INTO events VALUES (?1, ?2, ?3, ?4)",
+                    params![e.height as i64, e.tx_hash, e.event.identity(), serde_json::to_string(&e.event)?],
+                )?,
+            };
+        }
+        if let Some(state) = state {
+            tx.execute("INSERT OR REPLACE INTO meta VALUES ('state', ?1)", params![state])?;
+        }
+        tx.execute("INSERT OR REPLACE INTO meta VALUES ('last_height', ?1)", params![height as i64])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn roots(&self, identity: &str) -> Result<Vec<RootRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT * FROM roots WHERE identity = ?1 ORDER BY rowid")?;
+        let rows = stmt.query_map([identity], |row| {
+            Ok(RootRecord {
+                height: row.get::<_, i64>(0)? as u64,
+                tx_hash: row.get(1)?,
+                identity: row.get(2)?,
+                root: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn commitments(&self, identity: &str) -> Result<Vec<CommitmentRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT * FROM commitments WHERE identity = ?1 ORDER BY rowid")?;
+        let rows = stmt.query_map([identity], |row| {
+            Ok(CommitmentRecord {
+                height: row.get::<_, i64>(0)? as u64,
+                tx_hash: row.get(1)?,
+                identity: row.get(2)?,
+                public_key: row.get(3)?,
+                commitment: row.get(4)?,
+                ciphertexts: row.get::<_, i64>(5)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn key_events(&self, identity: &str) -> Result<Vec<KeyEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT * FROM key_events WHERE identity = ?1 ORDER BY rowid")?;
+        let rows = stmt.query_map([identity], |row| {
+            Ok(KeyEvent {
+                height: row.get::<_, i64>(0)? as u64,
+                tx_hash: row.get(1)?,
+                identity: row.get(2)?,
+                kind: row.get(3)?,
+                public_key: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Events of blobs sent by `identity`
+    pub fn events(&self, identity: &str) -> Result<Vec<EventRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT height, tx_hash, event FROM events WHERE identity = ?1 ORDER BY rowid")?;
+        let rows = stmt.query_map([identity], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?;
+        let mut events = Vec::new();
+        for row in rows {
+            let (height, tx_hash, event) = row?;
+            events.push(EventRecord {
+                height,
+                tx_hash,
+                event: serde_json::from_str(&event)?,
+            });
+        }
+        Ok(events)
+    }
+
+    /// Sessions answering `query_commitment`, or all of them
+    pub fn sessions(&self, query_commitment: Option<&str>) -> Result<Vec<SessionRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM sessions WHERE ?1 IS NULL OR query_commitment = ?1 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([query_commitment], |row| {
+            Ok(SessionRecord {
+                height: row.get::<_, i64>(0)? as u64,
+                tx_hash: row.get(1)?,
+                server: row.get(2)?,
+                query_commitment: row.get(3)?,
+                differences: row.get::<_, i64>(4)? as usize,
+                differences_hash: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
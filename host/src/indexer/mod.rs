@@ -0,0 +1,303 @@
+// host/src/indexer/mod.rs
+
+//! Local index of the Meetup contract's history.
+//!
+//! The node only keeps the current contract state, which forgets earlier roots,
+//! commitments and PSI sessions. The [`Indexer`] follows the blocks the node
+//! settles, derives a record from every successful Meetup blob and stores it in
+//! SQLite, block by block, along with the cursor it resumes from.
+
+pub mod db;
+pub mod source;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use contract::{InterestLayout, Meetup, MeetupAction};
+
+use db::{CommitmentRecord, EventRecord, IndexDb, KeyEvent, Record, RootRecord, SessionRecord};
+use source::{NodeSource, SettledBlob};
+
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct Indexer {
+    source: Box<dyn NodeSource>,
+    db: Arc<IndexDb>,
+    contract_name: String,
+}
+
+impl Indexer {
+    pub fn new(source: Box<dyn NodeSource>, db: Arc<IndexDb>, contract_name: &str) -> Self {
+        Indexer {
+            source,
+            db,
+            contract_name: contract_name.to_string(),
+        }
+    }
+
+    /// Indexes every block settled since the last call. Returns the number of
+    /// blocks indexed.
+    pub async fn sync(&self) -> Result<u64> {
+        let latest = self.source.latest_height().await?;
+        let start = self.db.last_height()?.map_or(0, |h| h + 1);
+        for height in start..=latest {
+            let blobs = self.source.settled_blobs(&self.contract_name, height).await?;
+            let records: Vec<Record> = blobs
+                .iter()
+                .flat_map(|blob| record_of(blob).into_iter().chain(event_record(blob)))
+                .collect();
+            let state = match blobs.last() {
+                Some(blob) => Some(blob.next_state.as_bytes()?),
+                None => None,
+            };
+            self.db.store_block(height, &records, state.as_deref())?;
+        }
+        Ok((latest + 1).saturating_sub(start))
+    }
+
+    /// Keeps syncing until an error occurs.
+    pub async fn follow(&self) -> Result<()> {
+        loop {
+            let indexed = self.sync().await?;
+            if indexed > 0 {
+                println!("📚 Indexed {} blocks", indexed);
+            }
+            tokio::time::sleep(FOLLOW_INTERVAL).await;
+        }
+    }
+
+    /// Contract state after the last indexed Meetup blob
+    pub fn state(&self) -> Result<Option<Meetup>> {
+        Ok(match self.db.state()? {
+            Some(bytes) => Some(borsh::from_slice(&bytes)?),
+            None => None,
+        })
+    }
+}
+
+/// The key's own hex encoding; its scheme shows in the commitment of its ciphertexts
+fn hex_key(public_key: &contract::PublicKey) -> String {
+    public_key.to_string()
+}
+
+/// The event a settled blob reported, if its guest reports any
+fn event_record(blob: &SettledBlob) -> Option<Record> {
+    Some(Record::Event(EventRecord {
+        height: blob.height,
+        tx_hash: blob.tx_hash.clone(),
+        event: blob.event.clone()?,
+    }))
+}
+
+/// What a settled blob adds to the history; `None` for actions without one.
+fn record_of(blob: &SettledBlob) -> Option<Record> {
+    let (height, tx_hash, identity) = (blob.height, blob.tx_hash.clone(), blob.identity.clone());
+    let key_event = |kind: &str| {
+        Record::Key(KeyEvent {
+            height,
+            tx_hash: tx_hash.clone(),
+            identity: identity.clone(),
+            kind: kind.to_string(),
+            public_key: blob.next_state.public_keys.get(&blob.identity).map(hex_key),
+        })
+    };
+    Some(match &blob.action {
+        MeetupAction::PostRoot => Record::Root(RootRecord {
+            height,
+            tx_hash,
+            identity,
+            root: blob.next_state.merkle_roots.last()?.to_string(),
+        }),
+        MeetupAction::RegisterPublicKey { .. } => key_event("register"),
+        MeetupAction::RotateKey { .. } => key_event("rotate"),
+        MeetupAction::RevokeKey => key_event("revoke"),
+        MeetupAction::Migrate => return None,
+        MeetupAction::AddEncryption {
+            public_key,
+            ciphertexts,
+            layout,
+        } => Record::Commitment(CommitmentRecord {
+            height,
+            tx_hash,
+            identity,
+            public_key: hex_key(public_key),
+            commitment: Meetup::commitment(public_key, *layout, ciphertexts),
+            ciphertexts: ciphertexts.len(),
+        }),
+        MeetupAction::BlindDifferences {
+            public_key,
+            ciphertexts,
+            differences,
+        } => Record::Session(SessionRecord {
+            height,
+            tx_hash,
+            server: identity,
+            query_commitment: Meetup::commitment(public_key, InterestLayout::Single, ciphertexts),
+            differences: differences.len(),
+            differences_hash: blob.next_state.blinded_differences_hash.clone(),
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use contract::{Ciphertexts, MeetupEvent, PublicKey};
+    use crypto::{paillier::prepare_key, HomomorphicScheme, Paillier, PaillierPublicKey};
+
+    /// A node whose settled history is built by hand.
+    #[derive(Default)]
+    struct MockNode {
+        blocks: Mutex<HashMap<u64, Vec<SettledBlob>>>,
+        latest: Mutex<u64>,
+        state: Mutex<Option<Meetup>>,
+    }
+
+    impl MockNode {
+        /// Settles `action` by `identity` in a new block, applying `update` to the state.
+        fn settle(&self, identity: &str, action: MeetupAction, update: impl FnOnce(&mut Meetup)) {
+            let mut state = self.state.lock().unwrap();
+            let next = state.get_or_insert_with(Meetup::new);
+            update(next);
+            let event = match &action {
+                MeetupAction::PostRoot => Some(MeetupEvent::RootPosted {
+                    identity: identity.to_string(),
+                    root: *next.merkle_roots.last().unwrap(),
+                    index: next.merkle_roots.len() as u64 - 1,
+                }),
+                _ => None,
+            };
+            let mut latest = self.latest.lock().unwrap();
+            *latest += 1;
+            self.blocks.lock().unwrap().insert(
+                *latest,
+                vec![SettledBlob {
+                    height: *latest,
+                    tx_hash: format!("tx{}", *latest),
+                    identity: identity.to_string(),
+                    action,
+                    next_state: next.clone(),
+                    event,
+                }],
+            );
+        }
+
+        /// A block without Meetup blobs
+        fn empty_block(&self) {
+            *self.latest.lock().unwrap() += 1;
+        }
+    }
+
+    #[async_trait]
+    impl NodeSource for Arc<MockNode> {
+        async fn latest_height(&self) -> Result<u64> {
+            Ok(*self.latest.lock().unwrap())
+        }
+
+        async fn settled_blobs(&self, _contract_name: &str, height: u64) -> Result<Vec<SettledBlob>> {
+            Ok(self.blocks.lock().unwrap().get(&height).cloned().unwrap_or_default())
+        }
+    }
+
+    fn indexer(node: &Arc<MockNode>) -> (Indexer, Arc<IndexDb>) {
+        let db = Arc::new(IndexDb::in_memory().unwrap());
+        (Indexer::new(Box::new(node.clone()), db.clone(), "meetup"), db)
+    }
+
+    fn encrypt(pk: &PaillierPublicKey, values: &[u128]) -> Ciphertexts {
+        Ciphertexts::Paillier(values.iter().map(|&m| Paillier::encrypt(pk, m)).collect())
+    }
+
+    #[tokio::test]
+    async fn indexes_roots_keys_commitments_and_sessions() {
+        let node = Arc::new(MockNode::default());
+        let (paillier_pk, _) = prepare_key(7759, 6983);
+        let pk = PublicKey::Paillier(paillier_pk);
+        let query = encrypt(&paillier_pk, &[1, 2]);
+
+        node.settle("alice", MeetupAction::PostRoot, |s| s.merkle_roots.push(42));
+        node.empty_block();
+        node.settle("alice", MeetupAction::RegisterPublicKey { public_key: pk }, |s| {
+            s.public_keys.insert("alice".into(), pk);
+        });
+        let action = MeetupAction::AddEncryption {
+            public_key: pk,
+            ciphertexts: query.clone(),
+            layout: InterestLayout::Single,
+        };
+        node.settle("alice", action, |s| {
+            s.commitments.insert("alice".into(), Meetup::commitment(&pk, InterestLayout::Single, &query));
+        });
+        let action = MeetupAction::BlindDifferences {
+            public_key: pk,
+            ciphertexts: query.clone(),
+            differences: encrypt(&paillier_pk, &[0, 5]),
+        };
+        node.settle("bob", action, |s| s.blinded_differences_hash = "abc".into());
+        node.settle("alice", MeetupAction::RevokeKey, |s| {
+            s.public_keys.remove("alice");
+            s.commitments.remove("alice");
+        });
+
+        let (indexer, db) = indexer(&node);
+        assert_eq!(indexer.sync().await.unwrap(), 7);
+        assert_eq!(db.last_height().unwrap(), Some(6));
+
+        let roots = db.roots("alice").unwrap();
+        assert_eq!(roots.len(), 1);
+        assert_eq!((roots[0].height, roots[0].root.as_str()), (1, "42"));
+        let events: Vec<_> = db.events("alice").unwrap().into_iter().map(|e| (e.height, e.event)).collect();
+        let posted = MeetupEvent::RootPosted {
+            identity: "alice".into(),
+            root: 42,
+            index: 0,
+        };
+        assert_eq!(events, [(1, posted)]);
+
+        let keys = db.key_events("alice").unwrap();
+        let kinds: Vec<_> = keys.iter().map(|k| (k.kind.as_str(), k.public_key.is_some())).collect();
+        assert_eq!(kinds, [("register", true), ("revoke", false)]);
+
+        let commitments = db.commitments("alice").unwrap();
+        assert_eq!(commitments.len(), 1);
+        assert_eq!(commitments[0].commitment, Meetup::commitment(&pk, InterestLayout::Single, &query));
+        assert_eq!(commitments[0].ciphertexts, 2);
+
+        let sessions = db.sessions(Some(&commitments[0].commitment)).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!((sessions[0].server.as_str(), sessions[0].differences_hash.as_str()), ("bob", "abc"));
+        assert!(db.sessions(Some("other")).unwrap().is_empty());
+
+        // The state survives a revocation that emptied the live one of alice's entries
+        let state = indexer.state().unwrap().unwrap();
+        assert_eq!(state.merkle_roots, vec![42]);
+        assert!(state.public_keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_cursor() {
+        let node = Arc::new(MockNode::default());
+        let (indexer, db) = indexer(&node);
+        node.settle("alice", MeetupAction::PostRoot, |s| s.merkle_roots.push(1));
+        assert_eq!(indexer.sync().await.unwrap(), 2);
+
+        // Nothing new: nothing indexed twice
+        assert_eq!(indexer.sync().await.unwrap(), 0);
+        assert_eq!(db.roots("alice").unwrap().len(), 1);
+
+        node.settle("alice", MeetupAction::PostRoot, |s| s.merkle_roots.push(2));
+        assert_eq!(indexer.sync().await.unwrap(), 1);
+        let roots: Vec<_> = db.roots("alice").unwrap().into_iter().map(|r| r.root).collect();
+        assert_eq!(roots, ["1", "2"]);
+
+        // A fresh indexer over the same database picks up where the last one stopped
+        let again = Indexer::new(Box::new(node.clone()), db.clone(), "meetup");
+        assert_eq!(again.sync().await.unwrap(), 0);
+        assert_eq!(db.roots("alice").unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,123 @@
+// host/src/indexer/source.rs
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use contract::{Meetup, MeetupAction, MeetupEvent};
+use sdk::HyleOutput;
+use serde::Deserialize;
+
+/// A Meetup blob whose transaction settled successfully.
+#[derive(Debug, Clone)]
+pub struct SettledBlob {
+    pub height: u64,
+    pub tx_hash: String,
+    pub identity: String,
+    pub action: MeetupAction,
+    /// Contract state the blob's proof left behind
+    pub next_state: Meetup,
+    /// What the blob did, from the proof's program output; none for outputs
+    /// of guests older than the events
+    pub event: Option<MeetupEvent>,
+}
+
+/// Where the indexer reads settled history from.
+#[async_trait]
+pub trait NodeSource: Send + Sync {
+    /// Height of the last block the node settled
+    async fn latest_height(&self) -> Result<u64>;
+
+    /// The blobs of `contract_name` settled in block `height`, in settlement order.
+    async fn settled_blobs(&self, contract_name: &str, height: u64) -> Result<Vec<SettledBlob>>;
+}
+
+/// Reads the node's indexer REST API (`/v1/indexer/...`).
+pub struct HyleIndexerSource {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct ApiBlock {
+    height: u64,
+}
+
+#[derive(Deserialize)]
+struct ApiTransaction {
+    tx_hash: String,
+    transaction_status: String,
+}
+
+#[derive(Deserialize)]
+struct ApiBlob {
+    identity: String,
+    contract_name: String,
+    data: Vec<u8>,
+    proof_outputs: Vec<serde_json::Value>,
+}
+
+impl HyleIndexerSource {
+    pub fn new(url: &str) -> Self {
+        HyleIndexerSource {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T> {
+        let url = format!("{}/v1/indexer/{}", self.url, path);
+        self.client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Unexpected response from {}", url))
+    }
+
+    /// Status of transaction `tx_hash` ("Sequenced", "Success", "Failure",
+    /// "TimedOut"...), or `None` while the indexer hasn't seen it.
+    pub async fn transaction_status(&self, tx_hash: &str) -> Result<Option<String>> {
+        let url = format!("{}/v1/indexer/transaction/hash/{}", self.url, tx_hash);
+        let response = self.client.get(&url).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let tx: ApiTransaction = response
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Unexpected response from {}", url))?;
+        Ok(Some(tx.transaction_status))
+    }
+}
+
+#[async_trait]
+impl NodeSource for HyleIndexerSource {
+    async fn latest_height(&self) -> Result<u64> {
+        Ok(self.get::<ApiBlock>("block/last").await?.height)
+    }
+
+    async fn settled_blobs(&self, contract_name: &str, height: u64) -> Result<Vec<SettledBlob>> {
+        let txs: Vec<ApiTransaction> = self.get(&format!("transactions/block/{}", height)).await?;
+        let mut settled = Vec::new();
+        for tx in txs.into_iter().filter(|tx| tx.transaction_status == "Success") {
+            let blobs: Vec<ApiBlob> = self.get(&format!("blobs/hash/{}", tx.tx_hash)).await?;
+            for blob in blobs.into_iter().filter(|b| b.contract_name == contract_name) {
+                let Some(output) = blob.proof_outputs.into_iter().next() else {
+                    continue;
+                };
+                let output: HyleOutput = serde_json::from_value(output).context("Could not decode proof output")?;
+                settled.push(SettledBlob {
+                    height,
+                    tx_hash: tx.tx_hash.clone(),
+                    identity: blob.identity,
+                    action: borsh::from_slice(&blob.data).context("Could not decode Meetup blob")?,
+                    next_state: Meetup::try_from(output.next_state)?,
+                    event: MeetupEvent::from_program_output(&output.program_outputs).ok(),
+                });
+            }
+        }
+        Ok(settled)
+    }
+}
@@ -0,0 +1,434 @@
+// host/src/jobs.rs
+
+//! Background proving jobs.
+//!
+//! Proving can take minutes, so the HTTP endpoints hand their action to a
+//! [`JobQueue`] and answer with a job ID straight away. At most `workers` jobs
+//! run at once; the others wait in `Queued`. A job ends once its transaction
+//! settles or fails on the node. Clients poll `GET /jobs/{id}` and can cancel a
+//! job that hasn't finished. A job can carry a result, e.g. values its proof
+//! vouches for, which its status only shows once the job settles. Finished
+//! jobs are forgotten after
+//! [`FINISHED_JOB_TTL`], and beyond [`MAX_FINISHED_JOBS`] the oldest go first.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use contract::MeetupEvent;
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+use tokio::task::AbortHandle;
+
+use crate::api::{ActionHooks, Stage};
+use crate::settlement::Settlement;
+
+pub type JobId = u64;
+
+/// How long the status of a finished job stays available
+pub const FINISHED_JOB_TTL: Duration = Duration::from_secs(3600);
+/// Finished jobs kept at most
+pub const MAX_FINISHED_JOBS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for a free worker
+    Queued,
+    /// Executing the action against the settled state
+    Executing,
+    /// Blob sent, generating the proof
+    Proving,
+    /// Proof sent, waiting for the node to settle it
+    Submitted { proof_tx_hash: String },
+    /// The node applied the proven transition
+    Settled { proof_tx_hash: String },
+    /// The node had not settled the transaction when the settlement timeout ran out
+    TimedOut { proof_tx_hash: String },
+    Failed { error: String },
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Settled { .. } | JobState::TimedOut { .. } | JobState::Failed { .. } | JobState::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: JobId,
+    /// What the job does, e.g. "post_root"
+    pub kind: String,
+    #[serde(flatten)]
+    pub state: JobState,
+    /// What the job's proven actions did, once its proofs are sent
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<MeetupEvent>,
+    /// The job's result, once its transaction settled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+
+struct Job {
+    status: JobStatus,
+    /// Result withheld until the job settles
+    result: Option<serde_json::Value>,
+    abort: Option<AbortHandle>,
+    /// When the job reached a finished state
+    finished_at: Option<Instant>,
+}
+
+impl Job {
+    fn set_state(&mut self, state: JobState) {
+        if state.is_finished() && self.finished_at.is_none() {
+            self.finished_at = Some(Instant::now());
+        }
+        if matches!(state, JobState::Settled { .. }) {
+            self.status.result = self.result.take();
+        }
+        self.status.state = state;
+    }
+}
+
+type Jobs = Arc<Mutex<HashMap<JobId, Job>>>;
+
+#[derive(Debug)]
+pub enum SubmitError {
+    /// `max_pending` jobs are already queued or running
+    QueueFull,
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::QueueFull => write!(f, "Too many pending proving jobs, try again later"),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+#[derive(Debug, Clone)]
+pub enum CancelError {
+    NotFound,
+    AlreadyFinished(JobState),
+}
+
+/// Reports a job's progress through the pipeline's [`ActionHooks`].
+#[derive(Clone)]
+pub struct JobProgress {
+    id: JobId,
+    jobs: Jobs,
+}
+
+impl JobProgress {
+    fn set(&self, state: JobState) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&self.id) {
+            // A cancelled job stays cancelled even if its task gets one more step in
+            if job.status.state != JobState::Cancelled {
+                job.set_state(state);
+            }
+        }
+    }
+
+    /// Final state of a job whose pipeline ended in `settlement`.
+    fn finish(&self, settlement: Result<Settlement>) {
+        let proof_tx_hash = match self.jobs.lock().unwrap().get(&self.id).map(|j| &j.status.state) {
+            Some(JobState::Submitted { proof_tx_hash }) => proof_tx_hash.clone(),
+            _ => String::new(),
+        };
+        self.set(match settlement {
+            Ok(Settlement::Settled) => JobState::Settled { proof_tx_hash },
+            Ok(Settlement::TimedOut) => JobState::TimedOut { proof_tx_hash },
+            Ok(Settlement::Failed { reason }) => JobState::Failed { error: reason },
+            Err(err) => JobState::Failed {
+                error: format!("{:#}", err),
+            },
+        });
+    }
+}
+
+impl ActionHooks for JobProgress {
+    fn stage(&self, stage: &Stage) {
+        self.set(match stage {
+            Stage::Executing => JobState::Executing,
+            Stage::Proving => JobState::Proving,
+            Stage::Submitted { proof_tx_hash, events } => {
+                if let Some(job) = self.jobs.lock().unwrap().get_mut(&self.id) {
+                    job.status.events = events.clone();
+                }
+                JobState::Submitted {
+                    proof_tx_hash: proof_tx_hash.clone(),
+                }
+            }
+        });
+    }
+}
+
+pub struct JobQueue {
+    jobs: Jobs,
+    workers: Arc<Semaphore>,
+    max_pending: usize,
+    next_id: AtomicU64,
+    settle_timeout: Duration,
+    finished_ttl: Duration,
+    max_finished: usize,
+    /// Jobs outlive the request that queued them, so they run on the main runtime
+    runtime: Handle,
+}
+
+impl JobQueue {
+    /// Must be called from within the runtime jobs should run on.
+    pub fn new(workers: usize, max_pending: usize, settle_timeout: Duration) -> Self {
+        assert!(workers > 0, "A job queue needs at least one worker");
+        JobQueue {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            workers: Arc::new(Semaphore::new(workers)),
+            max_pending,
+            next_id: AtomicU64::new(1),
+            settle_timeout,
+            finished_ttl: FINISHED_JOB_TTL,
+            max_finished: MAX_FINISHED_JOBS,
+            runtime: Handle::current(),
+        }
+    }
+
+    /// How long jobs wait for their transaction to settle
+    pub fn settle_timeout(&self) -> Duration {
+        self.settle_timeout
+    }
+
+    /// Queues `job`, which gets a [`JobProgress`] to pass to the pipeline as
+    /// hooks and resolves to the settlement of its transaction.
+    pub fn submit<F, Fut>(&self, kind: &str, job: F) -> Result<JobId, SubmitError>
+    where
+        F: FnOnce(JobProgress) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Settlement>> + Send + 'static,
+    {
+        self.submit_with_result(kind, None, job)
+    }
+
+    /// Like [`submit`](Self::submit), but the job's status shows `result`
+    /// once, and only if, its transaction settles.
+    pub fn submit_with_result<F, Fut>(
+        &self,
+        kind: &str,
+        result: Option<serde_json::Value>,
+        job: F,
+    ) -> Result<JobId, SubmitError>
+    where
+        F: FnOnce(JobProgress) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Settlement>> + Send + 'static,
+    {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.evict(&mut jobs);
+        let pending = jobs.values().filter(|j| !j.status.state.is_finished()).count();
+        if pending >= self.max_pending {
+            return Err(SubmitError::QueueFull);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        jobs.insert(
+            id,
+            Job {
+                status: JobStatus {
+                    id,
+                    kind: kind.to_string(),
+                    state: JobState::Queued,
+                    events: Vec::new(),
+                    result: None,
+                },
+                result,
+                abort: None,
+                finished_at: None,
+            },
+        );
+
+        let progress = JobProgress {
+            id,
+            jobs: self.jobs.clone(),
+        };
+        let workers = self.workers.clone();
+        let task = self.runtime.spawn(async move {
+            let Ok(_permit) = workers.acquire_owned().await else {
+                return;
+            };
+            let settlement = job(progress.clone()).await;
+            progress.finish(settlement);
+        });
+        // Still holding the lock: the task can't have finished its bookkeeping yet
+        if let Some(job) = jobs.get_mut(&id) {
+            job.abort = Some(task.abort_handle());
+        }
+        Ok(id)
+    }
+
+    pub fn status(&self, id: JobId) -> Option<JobStatus> {
+        self.jobs.lock().unwrap().get(&id).map(|j| j.status.clone())
+    }
+
+    /// Stops a job that hasn't finished. A blob already sent for it stays
+    /// unproven on the node until it times out.
+    pub fn cancel(&self, id: JobId) -> Result<JobStatus, CancelError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id).ok_or(CancelError::NotFound)?;
+        if job.status.state.is_finished() {
+            return Err(CancelError::AlreadyFinished(job.status.state.clone()));
+        }
+        if let Some(abort) = job.abort.take() {
+            abort.abort();
+        }
+        job.set_state(JobState::Cancelled);
+        Ok(job.status.clone())
+    }
+
+    /// Forgets finished jobs older than `finished_ttl`, then the oldest
+    /// finished ones beyond `max_finished`.
+    fn evict(&self, jobs: &mut HashMap<JobId, Job>) {
+        jobs.retain(|_, job| job.finished_at.is_none_or(|at| at.elapsed() < self.finished_ttl));
+        let mut finished: Vec<(Instant, JobId)> = jobs
+            .iter()
+            .filter_map(|(id, job)| job.finished_at.map(|at| (at, *id)))
+            .collect();
+        if finished.len() > self.max_finished {
+            finished.sort();
+            for (_, id) in &finished[..finished.len() - self.max_finished] {
+                jobs.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    async fn wait_until_finished(queue: &JobQueue, id: JobId) -> JobStatus {
+        for _ in 0..100 {
+            let status = queue.status(id).unwrap();
+            if status.state.is_finished() {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Job {} did not finish", id);
+    }
+
+    fn submitted(progress: &JobProgress) {
+        progress.stage(&Stage::Submitted {
+            proof_tx_hash: "ab".repeat(32),
+            events: vec![MeetupEvent::KeyRevoked { identity: "alice".into() }],
+        });
+    }
+
+    #[tokio::test]
+    async fn jobs_report_their_stages_and_outcome() {
+        let queue = JobQueue::new(1, 4, Duration::from_secs(1));
+        let settled = queue
+            .submit("post_root", |progress| async move {
+                progress.stage(&Stage::Proving);
+                submitted(&progress);
+                Ok(Settlement::Settled)
+            })
+            .unwrap();
+        let status = wait_until_finished(&queue, settled).await;
+        assert_eq!(status.kind, "post_root");
+        assert_eq!(status.state, JobState::Settled { proof_tx_hash: "ab".repeat(32) });
+        assert_eq!(status.events.len(), 1);
+
+        let failed = queue
+            .submit("post_root", |_| async move { anyhow::bail!("No such contract") })
+            .unwrap();
+        let status = wait_until_finished(&queue, failed).await;
+        assert_eq!(status.state, JobState::Failed { error: "No such contract".into() });
+        assert!(status.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn results_are_withheld_until_the_job_settles() {
+        let queue = JobQueue::new(1, 4, Duration::from_secs(1));
+        let (release, released) = oneshot::channel::<()>();
+        let result = Some(serde_json::json!({ "differences": ["00"] }));
+        let settled = queue
+            .submit_with_result("blind_differences", result.clone(), |progress| async move {
+                submitted(&progress);
+                let _ = released.await;
+                Ok(Settlement::Settled)
+            })
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(queue.status(settled).unwrap().result.is_none());
+        release.send(()).unwrap();
+        assert_eq!(wait_until_finished(&queue, settled).await.result, result);
+
+        let failed = queue
+            .submit_with_result("blind_differences", result, |_| async move {
+                Ok(Settlement::Failed { reason: "Bad blinding".into() })
+            })
+            .unwrap();
+        assert!(wait_until_finished(&queue, failed).await.result.is_none());
+    }
+
+    #[tokio::test]
+    async fn full_queues_refuse_jobs_and_cancelled_jobs_stay_cancelled() {
+        let queue = JobQueue::new(1, 2, Duration::from_secs(1));
+        let (release, released) = oneshot::channel::<()>();
+        let running = queue
+            .submit("post_root", |_| async move {
+                let _ = released.await;
+                Ok(Settlement::Settled)
+            })
+            .unwrap();
+        let queued = queue.submit("post_root", |_| async move { Ok(Settlement::Settled) }).unwrap();
+        assert!(matches!(
+            queue.submit("post_root", |_| async move { Ok(Settlement::Settled) }),
+            Err(SubmitError::QueueFull)
+        ));
+        assert_eq!(queue.status(queued).unwrap().state, JobState::Queued);
+
+        assert_eq!(queue.cancel(running).unwrap().state, JobState::Cancelled);
+        drop(release);
+        // The cancelled job freed its worker for the queued one
+        assert!(matches!(wait_until_finished(&queue, queued).await.state, JobState::Settled { .. }));
+        assert_eq!(queue.status(running).unwrap().state, JobState::Cancelled);
+        assert!(matches!(queue.cancel(running), Err(CancelError::AlreadyFinished(JobState::Cancelled))));
+        assert!(matches!(queue.cancel(999), Err(CancelError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn finished_jobs_are_evicted() {
+        let mut queue = JobQueue::new(1, 4, Duration::from_secs(1));
+        queue.max_finished = 1;
+        let first = queue.submit("post_root", |_| async move { Ok(Settlement::TimedOut) }).unwrap();
+        wait_until_finished(&queue, first).await;
+        let second = queue.submit("post_root", |_| async move { Ok(Settlement::TimedOut) }).unwrap();
+        wait_until_finished(&queue, second).await;
+
+        // Over the cap: the oldest finished job goes
+        let (release, released) = oneshot::channel::<()>();
+        let running = queue
+            .submit("post_root", |_| async move {
+                let _ = released.await;
+                Ok(Settlement::Settled)
+            })
+            .unwrap();
+        assert!(queue.status(first).is_none());
+        assert!(queue.status(second).is_some());
+
+        // Past the TTL every finished job goes, but never an unfinished one
+        queue.finished_ttl = Duration::ZERO;
+        queue.submit("post_root", |_| async move { Ok(Settlement::Settled) }).unwrap();
+        assert!(queue.status(second).is_none());
+        assert!(queue.status(running).is_some());
+        drop(release);
+    }
+}
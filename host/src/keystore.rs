@@ -0,0 +1,415 @@
+// host/src/keystore.rs
+
+//! Encrypted local storage for Paillier and ElGamal keypairs.
+//!
+//! Each key lives in `<profile dir>/keys/<name>.json`, along with its scheme
+//! (Paillier for files that predate ElGamal keys). The public key is stored
+//! in the clear so it can be used without the passphrase; the secret key is
+//! sealed with ChaCha20-Poly1305 under a key derived from the passphrase with
+//! Argon2id. The key name and public key are bound to the ciphertext as
+//! associated data, so a sealed secret key can't be swapped between entries.
+//!
+//! The keys themselves are demo-strength. Paillier primes have `PRIME_BITS` =
+//! 15 bits, so `n` has about 30 bits and factors instantly, and ElGamal keys
+//! live in a 61-bit group whose discrete logarithms are about as easy. Drawing
+//! them from the OS random generator makes them unpredictable, not hard to
+//! break: they show the flow without keeping interests secret.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use contract::{PublicKey, SchemeKind};
+use crypto::paillier::{are_valid_primes, prepare_key, PRIME_BITS};
+use crypto::{ElGamal, ElGamalParams, ElGamalSecretKey, HomomorphicScheme, PaillierPublicKey, PaillierSecretKey};
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::psi::{ELGAMAL_G, ELGAMAL_P};
+
+/// Version of the on-disk key file format
+const KEY_FILE_VERSION: u8 = 1;
+/// Environment variable read before prompting for the passphrase
+pub const PASSPHRASE_ENV: &str = "MEETUP_KEYSTORE_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    version: u8,
+    #[serde(default)]
+    scheme: SchemeKind,
+    /// Hex encoded, in the encoding of `scheme`
+    public_key: String,
+    kdf: KdfParams,
+    /// Hex encoded
+    nonce: String,
+    /// Hex encoded AEAD ciphertext of the borsh encoded secret key
+    sealed_secret_key: String,
+}
+
+/// Argon2id parameters, stored per key so they can be raised without breaking old files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KdfParams {
+    /// Hex encoded
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl KdfParams {
+    fn fresh() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        KdfParams {
+            salt: hex::encode(salt),
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<Key> {
+        let salt = hex::decode(&self.salt).context("Invalid salt")?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
+
+/// A secret key of any scheme
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretKey {
+    Paillier(PaillierSecretKey),
+    Elgamal(ElGamalSecretKey),
+}
+
+impl SecretKey {
+    pub fn public_key(&self) -> PublicKey {
+        match self {
+            SecretKey::Paillier(sk) => PublicKey::Paillier(sk.public_key()),
+            SecretKey::Elgamal(sk) => PublicKey::Elgamal(sk.public_key()),
+        }
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            SecretKey::Paillier(sk) => borsh::to_vec(sk)?,
+            SecretKey::Elgamal(sk) => borsh::to_vec(sk)?,
+        })
+    }
+
+    fn from_bytes(scheme: SchemeKind, bytes: &[u8]) -> Result<Self> {
+        Ok(match scheme {
+            SchemeKind::Paillier => SecretKey::Paillier(borsh::from_slice(bytes)?),
+            SchemeKind::Elgamal => SecretKey::Elgamal(borsh::from_slice(bytes)?),
+        })
+    }
+}
+
+impl KeyFile {
+    fn public_key(&self) -> Result<PublicKey> {
+        Ok(match self.scheme {
+            SchemeKind::Paillier => PublicKey::Paillier(self.public_key.parse()?),
+            SchemeKind::Elgamal => PublicKey::Elgamal(self.public_key.parse()?),
+        })
+    }
+}
+
+/// Associated data of a sealed secret key
+fn associated_data(name: &str, public_key: &PublicKey) -> Vec<u8> {
+    // Paillier keys keep the associated data of the files that predate schemes
+    match public_key.kind() {
+        SchemeKind::Paillier => format!("meetup-keystore:{}:{}", name, public_key).into_bytes(),
+        kind => format!("meetup-keystore:{}:{}:{}", name, kind, public_key).into_bytes(),
+    }
+}
+
+/// Draws a random prime of exactly `PRIME_BITS` bits.
+fn random_prime<R: Rng>(rng: &mut R) -> u128 {
+    loop {
+        // Top bit set for the exact length, low bit set for odd
+        let candidate = rng.gen_range(0..1u128 << (PRIME_BITS - 1)) | (1 << (PRIME_BITS - 1)) | 1;
+        if crypto::arith::is_prime(candidate) {
+            return candidate;
+        }
+    }
+}
+
+/// A fresh, demo-strength Paillier keypair from `PRIME_BITS`-bit primes drawn
+/// from the OS random generator.
+pub fn generate_keypair() -> (PaillierPublicKey, PaillierSecretKey) {
+    let mut rng = OsRng;
+    loop {
+        let (p, q) = (random_prime(&mut rng), random_prime(&mut rng));
+        if are_valid_primes(p, q) {
+            return prepare_key(p, q);
+        }
+    }
+}
+
+/// A fresh, demo-strength keypair of `scheme`; ElGamal keys live in the demo group of `psi`.
+pub fn generate_secret_key(scheme: SchemeKind) -> SecretKey {
+    match scheme {
+        SchemeKind::Paillier => SecretKey::Paillier(generate_keypair().1),
+        SchemeKind::Elgamal => {
+            let params = ElGamalParams {
+                p: ELGAMAL_P,
+                g: ELGAMAL_G,
+                x: OsRng.gen_range(2..=ELGAMAL_P - 2),
+            };
+            SecretKey::Elgamal(ElGamal::keygen(&params).1)
+        }
+    }
+}
+
+/// The passphrase from `MEETUP_KEYSTORE_PASSPHRASE`, or prompted on the terminal.
+pub fn read_passphrase() -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    rpassword::prompt_password("Keystore passphrase: ").context("Could not read the passphrase")
+}
+
+/// The directory of `profile` under the platform data directory.
+pub fn profile_dir(profile: &str) -> Result<PathBuf> {
+    let base = dirs::data_dir().context("No data directory on this platform")?;
+    Ok(base.join("meetup").join(profile))
+}
+
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    /// The keystore of a profile directory.
+    pub fn at(profile_dir: impl Into<PathBuf>) -> Self {
+        Keystore {
+            dir: profile_dir.into().join("keys"),
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        ensure!(
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "Key names may only contain letters, digits, '-' and '_'"
+        );
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    /// Generates a keypair of `scheme` and stores it as `name`. Never overwrites an existing key.
+    pub fn generate(&self, name: &str, passphrase: &str, scheme: SchemeKind) -> Result<PublicKey> {
+        let secret_key = generate_secret_key(scheme);
+        self.insert(name, &secret_key, passphrase)?;
+        Ok(secret_key.public_key())
+    }
+
+    /// Stores `secret_key` as `name`, sealed with `passphrase`.
+    pub fn insert(&self, name: &str, secret_key: &SecretKey, passphrase: &str) -> Result<()> {
+        let path = self.path(name)?;
+        if path.exists() {
+            bail!("A key named {} already exists", name);
+        }
+
+        let kdf = KdfParams::fresh();
+        let cipher = ChaCha20Poly1305::new(&kdf.derive_key(passphrase)?);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let public_key = secret_key.public_key();
+        let plaintext = secret_key.to_bytes()?;
+        let sealed = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &associated_data(name, &public_key),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Could not seal the secret key"))?;
+
+        let file = KeyFile {
+            version: KEY_FILE_VERSION,
+            scheme: public_key.kind(),
+            public_key: public_key.to_string(),
+            kdf,
+            nonce: hex::encode(nonce),
+            sealed_secret_key: hex::encode(sealed),
+        };
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Could not create {}", self.dir.display()))?;
+        write_private(&path, serde_json::to_string_pretty(&file)?.as_bytes())
+    }
+
+    fn read(&self, name: &str) -> Result<KeyFile> {
+        let path = self.path(name)?;
+        let data = fs::read_to_string(&path).with_context(|| format!("No key named {}", name))?;
+        let file: KeyFile = serde_json::from_str(&data).with_context(|| format!("Corrupted key file {}", path.display()))?;
+        ensure!(
+            file.version == KEY_FILE_VERSION,
+            "Unsupported key file version {} (expected {})",
+            file.version,
+            KEY_FILE_VERSION
+        );
+        Ok(file)
+    }
+
+    /// The public key stored as `name`; no passphrase needed.
+    pub fn public_key(&self, name: &str) -> Result<PublicKey> {
+        self.read(name)?.public_key()
+    }
+
+    /// Unseals the keypair stored as `name`.
+    pub fn load(&self, name: &str, passphrase: &str) -> Result<(PublicKey, SecretKey)> {
+        let file = self.read(name)?;
+        let public_key = file.public_key()?;
+        let cipher = ChaCha20Poly1305::new(&file.kdf.derive_key(passphrase)?);
+        let nonce = hex::decode(&file.nonce).context("Invalid nonce")?;
+        ensure!(nonce.len() == NONCE_LEN, "Invalid nonce length");
+        let sealed = hex::decode(&file.sealed_secret_key).context("Invalid sealed secret key")?;
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &sealed,
+                    aad: &associated_data(name, &public_key),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Wrong passphrase or tampered key file for {}", name))?;
+        let secret_key = SecretKey::from_bytes(file.scheme, &plaintext)?;
+        ensure!(
+            secret_key.public_key() == public_key,
+            "Secret key of {} does not match its public key",
+            name
+        );
+        Ok((public_key, secret_key))
+    }
+
+    /// Names of the stored keys, sorted.
+    pub fn list(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+/// Writes a file only the current user can read.
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Could not create {}", path.display()))?;
+    std::io::Write::write_all(&mut file, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keystore(test: &str) -> Keystore {
+        let dir = std::env::temp_dir().join(format!("meetup-keystore-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Keystore::at(dir)
+    }
+
+    fn edit_file(keystore: &Keystore, name: &str, edit: impl FnOnce(&mut KeyFile)) {
+        let mut file = keystore.read(name).unwrap();
+        edit(&mut file);
+        let path = keystore.path(name).unwrap();
+        fs::write(path, serde_json::to_string_pretty(&file).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn sealed_keys_unseal_with_their_passphrase() {
+        let keystore = keystore("roundtrip");
+        let public_key = keystore.generate("alice", "correct horse", SchemeKind::Paillier).unwrap();
+        let (loaded, secret_key) = keystore.load("alice", "correct horse").unwrap();
+        assert_eq!(loaded, public_key);
+        assert_eq!(secret_key.public_key(), public_key);
+        assert_eq!(keystore.public_key("alice").unwrap(), public_key);
+        assert_eq!(keystore.list().unwrap(), vec!["alice".to_string()]);
+        assert!(keystore.generate("alice", "correct horse", SchemeKind::Paillier).is_err());
+
+        let public_key = keystore.generate("bob", "correct horse", SchemeKind::Elgamal).unwrap();
+        assert_eq!(public_key.kind(), SchemeKind::Elgamal);
+        let (loaded, secret_key) = keystore.load("bob", "correct horse").unwrap();
+        assert_eq!((loaded, secret_key.public_key()), (public_key, public_key));
+    }
+
+    #[test]
+    fn files_without_a_scheme_hold_paillier_keys() {
+        let keystore = keystore("legacy");
+        let public_key = keystore.generate("alice", "correct horse", SchemeKind::Paillier).unwrap();
+        let path = keystore.path("alice").unwrap();
+        let mut file: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        file.as_object_mut().unwrap().remove("scheme");
+        fs::write(&path, file.to_string()).unwrap();
+        assert_eq!(keystore.load("alice", "correct horse").unwrap().0, public_key);
+
+        // Claiming another scheme doesn't reinterpret the key
+        edit_file(&keystore, "alice", |file| file.scheme = SchemeKind::Elgamal);
+        assert!(keystore.load("alice", "correct horse").is_err());
+    }
+
+    #[test]
+    fn wrong_passphrases_are_refused() {
+        let keystore = keystore("passphrase");
+        keystore.generate("alice", "correct horse", SchemeKind::Paillier).unwrap();
+        let err = keystore.load("alice", "battery staple").unwrap_err();
+        assert!(err.to_string().contains("Wrong passphrase"));
+    }
+
+    #[test]
+    fn tampered_key_files_are_refused() {
+        let keystore = keystore("tampered");
+        keystore.generate("alice", "correct horse", SchemeKind::Paillier).unwrap();
+        keystore.generate("bob", "correct horse", SchemeKind::Paillier).unwrap();
+
+        // A flipped bit of the sealed secret key
+        edit_file(&keystore, "alice", |file| {
+            let mut sealed = hex::decode(&file.sealed_secret_key).unwrap();
+            sealed[0] ^= 1;
+            file.sealed_secret_key = hex::encode(sealed);
+        });
+        assert!(keystore.load("alice", "correct horse").is_err());
+
+        // Another public key in the clear, which is part of the associated data
+        let other = keystore.public_key("alice").unwrap();
+        edit_file(&keystore, "bob", |file| file.public_key = other.to_string());
+        assert!(keystore.load("bob", "correct horse").is_err());
+
+        // A sealed key copied under another name
+        keystore.generate("carol", "correct horse", SchemeKind::Elgamal).unwrap();
+        fs::copy(keystore.path("carol").unwrap(), keystore.path("dave").unwrap()).unwrap();
+        assert!(keystore.load("carol", "correct horse").is_ok());
+        assert!(keystore.load("dave", "correct horse").is_err());
+    }
+}
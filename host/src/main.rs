@@ -3,10 +3,38 @@
 use actix_web::{post, web, HttpResponse, Responder};
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use contract::{InterestLayout, MeetupAction, SchemeKind};
+use sdk::ProofData;
 use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
+mod aggregate;
 mod api;
+mod bench;
 mod http_server;
+mod indexer;
+mod jobs;
+mod keystore;
+mod pending;
+mod proof;
+mod proof_cache;
+mod prover;
+mod psi;
+mod settlement;
+mod simulator;
+
+use aggregate::AggregationConfig;
+use api::{NoHooks, Submission};
+use indexer::db::IndexDb;
+use indexer::source::HyleIndexerSource;
+use indexer::Indexer;
+use keystore::Keystore;
+use prover::{Prover, ProverBackend, ProverKind};
+use proof_cache::CachingProver;
+use simulator::{ReceiptCheck, Simulator};
+use settlement::Settlement;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -25,6 +53,140 @@ struct Cli {
   /// Contract name (default: counter)
   #[arg(long, default_value = "counter")]
   contract_name: String,
+
+  /// Keystore profile, stored under the platform data directory (default: default)
+  #[arg(long, default_value = "default")]
+  profile: String,
+
+  /// Profile directory to use instead of the one derived from --profile
+  #[arg(long)]
+  profile_dir: Option<PathBuf>,
+
+  /// Keystore entry the HTTP server runs the PSI under, in that key's scheme (default: server)
+  #[arg(long, default_value = "server")]
+  key: String,
+
+  /// Seconds to wait for a submitted transaction to settle; 0 to return once the proof is sent (default: 60)
+  #[arg(long, default_value_t = 60)]
+  settle_timeout: u64,
+
+  /// Proving jobs the HTTP server runs at once (default: 2)
+  #[arg(long, default_value_t = 2)]
+  prover_workers: usize,
+
+  /// Queued and running proving jobs beyond which the HTTP server refuses new ones (default: 32)
+  #[arg(long, default_value_t = 32)]
+  max_pending_jobs: usize,
+
+  /// Backend the proofs are generated with (default: local, or execute with --simulate)
+  #[arg(long, value_enum)]
+  prover: Option<ProverKind>,
+
+  /// URL of the proving service for `--prover remote`
+  #[arg(long)]
+  prover_url: Option<String>,
+
+  /// Directory of cached proofs (default: proofs in the profile directory)
+  #[arg(long)]
+  proof_cache: Option<PathBuf>,
+
+  /// Always prove from scratch instead of reusing cached proofs
+  #[arg(long)]
+  no_proof_cache: bool,
+
+  /// Fold the proofs of several submissions, up to this many, into one aggregate proof transaction
+  #[arg(long)]
+  aggregate: Option<usize>,
+
+  /// Seconds a proof waits for others to be aggregated with (default: 5)
+  #[arg(long, default_value_t = 5)]
+  aggregate_wait: u64,
+
+  /// Write the hex encoded proofs of each submission to this file, one line per blob, for export-proof
+  #[arg(long)]
+  proof_out: Option<PathBuf>,
+
+  /// Node URL the indexer reads settled blocks from (default: --host)
+  #[arg(long)]
+  indexer_url: Option<String>,
+
+  /// SQLite database of the indexed history (default: index.sqlite in the profile directory)
+  #[arg(long)]
+  index_db: Option<PathBuf>,
+
+  /// In HTTP mode, serve an in-process ledger at --host instead of using a Hyle node
+  #[arg(long)]
+  simulate: bool,
+
+  /// With --simulate, only accept proofs whose receipt verifies
+  #[arg(long)]
+  verify_receipts: bool,
+
+  /// In HTTP mode, also follow the node and serve the indexed history
+  #[arg(long)]
+  index: bool,
+}
+
+impl Cli {
+  fn profile_dir(&self) -> Result<PathBuf> {
+    match &self.profile_dir {
+      Some(dir) => Ok(dir.clone()),
+      None => keystore::profile_dir(&self.profile),
+    }
+  }
+
+  fn prover_kind(&self) -> ProverKind {
+    match self.prover {
+      Some(kind) => kind,
+      None if self.simulate => ProverKind::Execute,
+      None => ProverKind::Local,
+    }
+  }
+
+  fn prover(&self) -> Result<Prover> {
+    let backend = self.prover_kind().backend(self.prover_url.as_deref())?;
+    if self.no_proof_cache {
+      return Ok(backend);
+    }
+    let dir = match &self.proof_cache {
+      Some(dir) => dir.clone(),
+      None => self.profile_dir()?.join("proofs"),
+    };
+    Ok(Arc::new(CachingProver::new(backend, dir)))
+  }
+
+  /// Aggregates proofs sent to `--host` with `--aggregate`; the node must be up.
+  async fn enable_aggregation(&self) -> Result<()> {
+    if let Some(max_proofs) = self.aggregate {
+      anyhow::ensure!(max_proofs > 0, "--aggregate needs at least one proof per aggregate");
+      aggregate::enable(&self.host, AggregationConfig {
+        max_proofs,
+        max_wait: Duration::from_secs(self.aggregate_wait),
+      }).await?;
+    }
+    Ok(())
+  }
+
+  fn keystore(&self) -> Result<Keystore> {
+    Ok(Keystore::at(self.profile_dir()?))
+  }
+
+  fn index_db(&self) -> Result<IndexDb> {
+    let path = match &self.index_db {
+      Some(path) => path.clone(),
+      None => {
+        let dir = self.profile_dir()?;
+        std::fs::create_dir_all(&dir)?;
+        dir.join("index.sqlite")
+      }
+    };
+    IndexDb::open(&path)
+  }
+
+  fn indexer(&self, db: Arc<IndexDb>, contract_name: &str) -> Indexer {
+    let url = self.indexer_url.as_deref().unwrap_or(&self.host);
+    Indexer::new(Box::new(HyleIndexerSource::new(url)), db, contract_name)
+  }
 }
 
 #[derive(Subcommand)]
@@ -34,6 +196,79 @@ enum Commands {
   PostRoot {
       interests: String,
   },
+  /// Generate a fresh demo-strength keypair and store it in the keystore as `name`.
+  Keygen {
+      name: String,
+      /// Homomorphic encryption scheme of the key, which the PSIs under it run on (default: paillier)
+      #[arg(long, default_value_t = SchemeKind::Paillier)]
+      scheme: SchemeKind,
+  },
+  /// List the keys in the keystore.
+  ListKeys {},
+  /// Register the public key of keystore entry `key` for this identity.
+  RegisterKey {
+      key: String,
+  },
+  /// Rotate this identity's key end to end: generate `new_key` in the keystore,
+  /// replace `key` on chain, and re-commit `--interests` under the new key.
+  RotateKey {
+      key: String,
+      new_key: String,
+      /// Interests to re-encrypt under the new key (the old commitment is dropped)
+      #[arg(long)]
+      interests: Option<String>,
+      /// Scheme of the new key (default: the scheme of `key`)
+      #[arg(long)]
+      scheme: Option<SchemeKind>,
+  },
+  /// Revoke this identity's key and commitment.
+  RevokeKey {},
+  /// Upgrade the contract state from an older layout to the current one.
+  MigrateState {},
+  /// Commit encrypted interests under the public key of keystore entry `key`.
+  PostEnc {
+      key: String,
+      interests: String,
+  },
+  /// Submit several actions in one blob transaction, each proven on the state
+  /// the previous one leaves: `post-root:<interests>`, `register-key:<key>`,
+  /// `post-enc:<key>:<interests>`, `rotate-key:<key>` (an existing keystore
+  /// entry), `revoke-key` and `migrate-state`.
+  Batch {
+      #[arg(required = true)]
+      actions: Vec<String>,
+  },
+  /// Index the contract's settled history into the local database.
+  Index {
+      /// Keep following new blocks instead of stopping once caught up
+      #[arg(long)]
+      follow: bool,
+  },
+  /// Serve the `--prover` backend as a proving service for `--prover remote`.
+  ServeProver {
+      #[arg(long, default_value_t = 8090)]
+      port: u16,
+  },
+  /// Bundle a proof (a file with the hex encoded or raw receipt) with its
+  /// journal, image ID and public inputs, for offline verification.
+  ExportProof {
+      proof: PathBuf,
+      #[arg(long)]
+      out: PathBuf,
+      /// Line of the proof, in a --proof-out file of a batch
+      #[arg(long, default_value_t = 0)]
+      blob: usize,
+  },
+  /// Verify a proof bundle against the Meetup guest and print the state
+  /// transition it proves. No node needed.
+  VerifyProof {
+      bundle: PathBuf,
+  },
+  /// Count the guest cycles of an AddEncryption under the naive and Montgomery
+  /// mod_exp, without proving or a node.
+  BenchAddEncryption {
+      interests: String,
+  },
 }
 
 #[derive(Deserialize)]
@@ -62,27 +297,202 @@ async fn register_contract_endpoint(
 #[post("/post-root")]
 async fn post_root_endpoint(
   req: web::Json<PostRootRequest>,
+  prover: web::Data<dyn ProverBackend>,
 ) -> impl Responder {
-  match api::post_root(&req.host, &req.contract_name, req.interests.clone()).await {
-    Ok(tx_hash) => HttpResponse::Ok().json(serde_json::json!({ "tx_hash": tx_hash })),
+  match api::post_root(&req.host, &req.contract_name, &prover.into_inner(), req.interests.clone(), &NoHooks).await {
+    Ok(submission) => HttpResponse::Ok().json(serde_json::json!({ "tx_hash": submission.blob_tx_hash })),
     Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
   }
 }
 
+/// Parses an action of `batch`, e.g. `post-enc:server:1 9 12 18`.
+fn batch_action(keystore: &Keystore, action: &str) -> Result<(MeetupAction, Vec<u8>)> {
+  let mut parts = action.splitn(3, ':');
+  let kind = parts.next().unwrap_or_default();
+  let mut arg = || parts.next().ok_or_else(|| anyhow::anyhow!("{} is missing an argument", kind));
+  Ok(match kind {
+    "post-root" => (MeetupAction::PostRoot, arg()?.as_bytes().to_vec()),
+    "register-key" => (
+      MeetupAction::RegisterPublicKey { public_key: keystore.public_key(arg()?)? },
+      vec![],
+    ),
+    "post-enc" => {
+      let public_key = keystore.public_key(arg()?)?;
+      api::add_encryption_action(&public_key, InterestLayout::Single, arg()?)?
+    }
+    "rotate-key" => (
+      MeetupAction::RotateKey { new_public_key: keystore.public_key(arg()?)? },
+      vec![],
+    ),
+    "revoke-key" => (MeetupAction::RevokeKey, vec![]),
+    "migrate-state" => (MeetupAction::Migrate, vec![]),
+    _ => anyhow::bail!("Unknown action {}", kind),
+  })
+}
+
+/// Waits for `submission` to settle (unless `--settle-timeout` is 0) and
+/// reports the outcome; anything but a settlement is an error.
+async fn settle(cli: &Cli, submission: &Submission) -> Result<()> {
+  if let Some(path) = &cli.proof_out {
+    let proofs: Vec<String> = submission.proofs.iter().map(|p| hex::encode(&p.0)).collect();
+    std::fs::write(path, proofs.join("\n"))?;
+    println!("📝 Proofs written to {}", path.display());
+  }
+  if cli.settle_timeout == 0 {
+    return Ok(());
+  }
+  println!("⏳ Waiting for blob tx {} to settle...", submission.blob_tx_hash);
+  let timeout = Duration::from_secs(cli.settle_timeout);
+  match settlement::wait_for_settlement(&cli.host, &cli.contract_name, submission, timeout).await? {
+    Settlement::Settled => {
+      println!("✅ Settled");
+      Ok(())
+    }
+    Settlement::Failed { reason } => anyhow::bail!("❌ {}", reason),
+    Settlement::TimedOut => anyhow::bail!("⌛ Not settled after {}s", cli.settle_timeout),
+  }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
   let cli = Cli::parse();
 
+  let keystore = cli.keystore()?;
+
+  if cli.simulate && cli.cli {
+    anyhow::bail!("--simulate only applies to the HTTP server: the ledger lives as long as the process");
+  }
+
   if cli.cli {
     // Run CLI mode.
-    match cli.command {
+    cli.enable_aggregation().await?;
+    match &cli.command {
       Some(Commands::RegisterContract {}) => {
         let tx_hash = api::register_contract(&cli.host, &cli.contract_name).await?;
         println!("✅ Register contract tx sent. Tx hash: {}", tx_hash);
       }
       Some(Commands::PostRoot { interests }) => {
-        let tx_hash = api::post_root(&cli.host, &cli.contract_name, interests).await?;
-        println!("✅ Proof tx sent. Tx hash: {}", tx_hash);
+        let submission = api::post_root(&cli.host, &cli.contract_name, &cli.prover()?, interests.clone(), &NoHooks).await?;
+        settle(&cli, &submission).await?;
+      }
+      Some(Commands::Keygen { name, scheme }) => {
+        let passphrase = keystore::read_passphrase()?;
+        let pk = keystore.generate(name, &passphrase, *scheme)?;
+        println!("🔑 Stored {} key {}. Public key: {}", scheme, name, pk);
+      }
+      Some(Commands::ListKeys {}) => {
+        for name in keystore.list()? {
+          let pk = keystore.public_key(&name)?;
+          println!("{} ({}): {}", name, pk.kind(), pk);
+        }
+      }
+      Some(Commands::PostEnc { key, interests }) => {
+        let public_key = keystore.public_key(key)?;
+        let submission = api::post_enc(&cli.host, &cli.contract_name, &cli.prover()?, &public_key, InterestLayout::Single, interests.clone(), &NoHooks).await?;
+        println!("Answers proof (attach to /receive-interests): {}", hex::encode(&submission.proof().0));
+        settle(&cli, &submission).await?;
+      }
+      Some(Commands::RegisterKey { key }) => {
+        let public_key = keystore.public_key(key)?;
+        let submission = api::register_key(&cli.host, &cli.contract_name, &cli.prover()?, &public_key, &NoHooks).await?;
+        settle(&cli, &submission).await?;
+      }
+      Some(Commands::RotateKey { key, new_key, interests, scheme }) => {
+        let registered = api::get_state(&cli.host, &cli.contract_name).await?
+          .public_keys
+          .get(&api::identity(&cli.contract_name))
+          .copied();
+        let public_key = keystore.public_key(key)?;
+        if registered != Some(public_key) {
+          anyhow::bail!("{} is not the key registered on chain", key);
+        }
+        let passphrase = keystore::read_passphrase()?;
+        let new_public_key = keystore.generate(new_key, &passphrase, scheme.unwrap_or(public_key.kind()))?;
+        println!("🔑 Stored key {}. Public key: {}", new_key, new_public_key);
+
+        let submission = api::rotate_key(&cli.host, &cli.contract_name, &cli.prover()?, &new_public_key, &NoHooks).await?;
+
+        match interests {
+          Some(interests) => {
+            // The AddEncryption is proven against the settled state, which must hold the new key
+            let timeout = cli.settle_timeout.max(1);
+            let settlement = settlement::wait_for_settlement(&cli.host, &cli.contract_name, &submission, Duration::from_secs(timeout)).await?;
+            if settlement != Settlement::Settled {
+              anyhow::bail!("The rotation did not settle ({:?}); run post-enc {} once it has", settlement, new_key);
+            }
+            println!("✅ Rotation settled");
+            let submission = api::post_enc(&cli.host, &cli.contract_name, &cli.prover()?, &new_public_key, InterestLayout::Single, interests.clone(), &NoHooks).await?;
+            println!("Answers proof (attach to /receive-interests): {}", hex::encode(&submission.proof().0));
+            settle(&cli, &submission).await?;
+          }
+          None => {
+            settle(&cli, &submission).await?;
+            println!("⚠️  No interests given: run post-enc {} before the next PSI", new_key);
+          }
+        }
+      }
+      Some(Commands::RevokeKey {}) => {
+        let submission = api::revoke_key(&cli.host, &cli.contract_name, &cli.prover()?, &NoHooks).await?;
+        settle(&cli, &submission).await?;
+      }
+      Some(Commands::Batch { actions }) => {
+        let actions = actions
+          .iter()
+          .map(|action| batch_action(&keystore, action))
+          .collect::<Result<Vec<_>>>()?;
+        let submission = api::submit_batch(&cli.host, &cli.contract_name, &cli.prover()?, actions, &NoHooks).await?;
+        settle(&cli, &submission).await?;
+      }
+      Some(Commands::MigrateState {}) => {
+        let submission = api::migrate_state(&cli.host, &cli.contract_name, &cli.prover()?, &NoHooks).await?;
+        settle(&cli, &submission).await?;
+      }
+      Some(Commands::Index { follow }) => {
+        let indexer = cli.indexer(Arc::new(cli.index_db()?), &cli.contract_name);
+        if *follow {
+          indexer.follow().await?;
+        } else {
+          println!("📚 Indexed {} blocks", indexer.sync().await?);
+        }
+      }
+      Some(Commands::ServeProver { port }) => {
+        if cli.prover_kind() == ProverKind::Remote {
+          anyhow::bail!("The proving service needs a local backend (--prover local or dev)");
+        }
+        prover::serve(cli.prover()?, *port).await?;
+      }
+      Some(Commands::ExportProof { proof, out, blob }) => {
+        let bytes = std::fs::read(proof)?;
+        let line = String::from_utf8_lossy(&bytes).lines().nth(*blob).map(|l| hex::decode(l.trim()));
+        let proof = match line {
+          Some(Ok(decoded)) => ProofData(decoded),
+          _ if *blob == 0 => ProofData(bytes),
+          _ => anyhow::bail!("No hex encoded proof on line {}", blob),
+        };
+        let bundle = proof::export_bundle(&proof, out)?;
+        println!("📦 Bundle of blob tx {} written to {}", bundle.public_inputs.tx_hash.0, out.display());
+      }
+      Some(Commands::VerifyProof { bundle }) => {
+        let transition = proof::verify_bundle(bundle)?;
+        let output = &transition.output;
+        let (before, after) = (&transition.initial_state, &transition.next_state);
+        println!("✅ Receipt verifies against the Meetup guest");
+        println!("Identity:     {}", output.identity.0);
+        println!("Blob tx:      {} (blob {})", output.tx_hash.0, output.index.0);
+        match proof::decode_event(output) {
+          Ok(Some(event)) => println!("Success:      true ({:?})", event),
+          _ => println!("Success:      {} ({})", output.success, String::from_utf8_lossy(&output.program_outputs)),
+        }
+        println!("Merkle roots: {:?} -> {:?}", before.merkle_roots, after.merkle_roots);
+        println!("Keys:         {:?} -> {:?}", before.public_keys, after.public_keys);
+        println!("Commitments:  {:?} -> {:?}", before.commitments, after.commitments);
+        println!("Differences:  {:?} -> {:?}", before.blinded_differences_hash, after.blinded_differences_hash);
+      }
+      Some(Commands::BenchAddEncryption { interests }) => {
+        let cycles = bench::add_encryption_cycles(interests)?;
+        println!("⏱️  AddEncryption, naive mod_exp:      {} cycles", cycles.naive);
+        println!("⏱️  AddEncryption, Montgomery mod_exp: {} cycles", cycles.montgomery);
+        println!("   Speedup: {:.2}x", cycles.naive as f64 / cycles.montgomery as f64);
       }
       None => {
         println!("No CLI command provided.");
@@ -90,7 +500,40 @@ async fn main() -> Result<()> {
     }
   } else {
     // Default: Run HTTP server mode.
-    http_server::run_server().await?;
+    let passphrase = keystore::read_passphrase()?;
+    // Fail at startup rather than on the first request
+    keystore.load(&cli.key, &passphrase)?;
+    let keys = http_server::ServerKeys {
+      keystore,
+      passphrase,
+      default_key: cli.key,
+    };
+    anyhow::ensure!(cli.prover_workers > 0, "--prover-workers needs at least one worker");
+    let jobs = jobs::JobQueue::new(cli.prover_workers, cli.max_pending_jobs, Duration::from_secs(cli.settle_timeout));
+    if cli.simulate {
+      if cli.index {
+        anyhow::bail!("The simulated ledger has no history to index");
+      }
+      let addr = cli.host.trim_start_matches("http://").trim_end_matches('/');
+      let receipts = if cli.verify_receipts { ReceiptCheck::Verify } else { ReceiptCheck::Skip };
+      let ledger = Simulator::start(addr, receipts)?;
+      println!("🧪 Simulating the ledger at {} (receipts: {:?})", ledger.url(), receipts);
+    }
+    cli.enable_aggregation().await?;
+    let history = if cli.index {
+      let db = Arc::new(cli.index_db()?);
+      // The server's endpoints act on their own contract
+      let indexer = cli.indexer(db.clone(), http_server::CONTRACT_NAME);
+      tokio::spawn(async move {
+        if let Err(err) = indexer.follow().await {
+          eprintln!("❌ Indexer stopped: {:#}", err);
+        }
+      });
+      Some(db)
+    } else {
+      None
+    };
+    http_server::run_server(cli.host.clone(), keys, jobs, cli.prover()?, history).await?;
   }
 
   Ok(())
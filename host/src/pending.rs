@@ -0,0 +1,289 @@
+// host/src/pending.rs
+
+//! Transactions this host sent that the node hasn't settled yet.
+//!
+//! The node applies a contract's blob transactions in order, so an action has
+//! to be proven against the state every earlier pending transaction leaves
+//! behind, not against the last settled state. Each pending transaction is
+//! kept with what it takes to execute it again, and the predicted state is the
+//! settled state with the still-pending transactions replayed on top.
+//!
+//! When a predecessor fails, replaying without it gives its successors another
+//! starting state than the one they were proven on; [`reprove_if_stale`]
+//! proves them again against it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use anyhow::{Context, Result};
+use client_sdk::rest_client::NodeApiHttpClient;
+use contract::Meetup;
+use sdk::{Blob, BlobIndex, ContractInput, Digestable, HyleContract, StateDigest, TxHash};
+use tokio::sync::OwnedMutexGuard;
+
+use crate::api::{prove_and_send, NoHooks};
+use crate::prover::Prover;
+
+pub struct PendingTx {
+    pub blob_tx_hash: TxHash,
+    pub identity: String,
+    pub blobs: Vec<Blob>,
+    /// Private input of each blob
+    pub private_inputs: Vec<Vec<u8>>,
+    /// State the latest proof of the first blob was built on
+    pub proven_on: StateDigest,
+    /// Backend the transaction was proven with, and is re-proven with
+    pub prover: Prover,
+}
+
+/// One blob's step of a transaction's execution.
+pub struct BlobStep {
+    pub inputs: ContractInput,
+    pub initial_state: StateDigest,
+    pub next_state: StateDigest,
+}
+
+/// Executes blob `index` of a transaction on `state`, as the guest would.
+pub fn execute_blob(
+    identity: &str,
+    blob_tx_hash: &TxHash,
+    blobs: &[Blob],
+    index: usize,
+    private_input: &[u8],
+    state: &mut Meetup,
+) -> Result<BlobStep> {
+    let inputs = ContractInput {
+        state: state.as_bytes()?,
+        identity: identity.to_string().into(),
+        tx_hash: blob_tx_hash.clone(),
+        private_input: private_input.to_vec(),
+        tx_ctx: None,
+        blobs: blobs.to_vec(),
+        index: BlobIndex(index),
+    };
+    let initial_state = state.as_digest();
+    state
+        .execute(&inputs)
+        .map_err(|e| anyhow::anyhow!("Blob {} fails: {}", index, e))?;
+    Ok(BlobStep {
+        inputs,
+        initial_state,
+        next_state: state.as_digest(),
+    })
+}
+
+/// Executes the blobs of a transaction in order, each on the state the previous
+/// one left behind, as the node applies them. Fails if any blob does: the node
+/// then rejects the whole transaction.
+pub fn execute_blobs(
+    identity: &str,
+    blob_tx_hash: &TxHash,
+    blobs: &[Blob],
+    private_inputs: &[Vec<u8>],
+    mut state: Meetup,
+) -> Result<(Vec<BlobStep>, Meetup)> {
+    let steps = private_inputs
+        .iter()
+        .enumerate()
+        .map(|(index, private_input)| execute_blob(identity, blob_tx_hash, blobs, index, private_input, &mut state))
+        .collect::<Result<_>>()?;
+    Ok((steps, state))
+}
+
+impl PendingTx {
+    fn execute(&self, state: Meetup) -> Result<(Vec<BlobStep>, Meetup)> {
+        execute_blobs(&self.identity, &self.blob_tx_hash, &self.blobs, &self.private_inputs, state)
+    }
+}
+
+#[derive(Default)]
+struct ContractTxs {
+    /// Held from reading the predicted state until the new transaction is
+    /// recorded, so concurrent submissions line up behind each other
+    sequencer: Arc<tokio::sync::Mutex<()>>,
+    txs: Vec<PendingTx>,
+}
+
+type Key = (String, String);
+
+static PENDING: LazyLock<Mutex<HashMap<Key, ContractTxs>>> = LazyLock::new(Default::default);
+
+/// Exclusive right to append to a contract's pending transactions.
+pub struct Slot {
+    key: Key,
+    _sequencer: OwnedMutexGuard<()>,
+}
+
+pub async fn reserve(host: &str, contract_name: &str) -> Slot {
+    let key = (host.to_string(), contract_name.to_string());
+    let sequencer = PENDING.lock().unwrap().entry(key.clone()).or_default().sequencer.clone();
+    Slot {
+        key,
+        _sequencer: sequencer.lock_owned().await,
+    }
+}
+
+impl Slot {
+    /// Drops the transactions that left the node's unsettled pool, then returns
+    /// the settled state with the remaining ones replayed, along with the state
+    /// each of them starts from.
+    async fn replay(&self, client: &NodeApiHttpClient) -> Result<(Meetup, Vec<(TxHash, Meetup)>)> {
+        let settled = Meetup::try_from(client.get_contract(&self.key.1.clone().into()).await?.state)?;
+
+        let hashes: Vec<TxHash> = self.with_txs(|txs| txs.iter().map(|tx| tx.blob_tx_hash.clone()).collect());
+        let mut gone = Vec::new();
+        for hash in hashes {
+            if !is_unsettled(&self.key.0, &hash).await? {
+                gone.push(hash);
+            }
+        }
+
+        self.with_txs(|txs| {
+            txs.retain(|tx| !gone.contains(&tx.blob_tx_hash));
+            let mut state = settled;
+            let mut starts = Vec::new();
+            for tx in txs.iter() {
+                starts.push((tx.blob_tx_hash.clone(), state.clone()));
+                // A transaction that fails here will fail on chain too: it leaves the state as is
+                if let Ok((_, next)) = tx.execute(state.clone()) {
+                    state = next;
+                }
+            }
+            Ok((state, starts))
+        })
+    }
+
+    /// The state the node will be in once every pending transaction settled.
+    pub async fn predicted_state(&self, client: &NodeApiHttpClient) -> Result<Meetup> {
+        Ok(self.replay(client).await?.0)
+    }
+
+    pub fn push(&self, tx: PendingTx) {
+        self.with_txs(|txs| txs.push(tx));
+    }
+
+    fn with_txs<T>(&self, f: impl FnOnce(&mut Vec<PendingTx>) -> T) -> T {
+        f(&mut PENDING.lock().unwrap().entry(self.key.clone()).or_default().txs)
+    }
+}
+
+/// Whether the node at `host` still has `hash` in its unsettled pool. Only a
+/// 404 means it left; any other failure is an error rather than a guess.
+async fn is_unsettled(host: &str, hash: &TxHash) -> Result<bool> {
+    let url = format!("{}/v1/unsettled_tx/{}", host.trim_end_matches('/'), hash);
+    let response = reqwest::get(&url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    response
+        .error_for_status()
+        .with_context(|| format!("Could not tell whether blob tx {} is still pending", hash))?;
+    Ok(true)
+}
+
+/// Proves `blob_tx_hash` again if the state it now starts from is not the one
+/// its proofs were built on, typically because a predecessor failed. Returns the
+/// state the new proofs lead to.
+pub async fn reprove_if_stale(host: &str, contract_name: &str, blob_tx_hash: &TxHash) -> Result<Option<StateDigest>> {
+    let client = NodeApiHttpClient::new(host.to_string())?;
+    let slot = reserve(host, contract_name).await;
+    let (_, starts) = slot.replay(&client).await?;
+    let Some((_, start)) = starts.into_iter().find(|(hash, _)| hash == blob_tx_hash) else {
+        // Settled or dropped in the meantime
+        return Ok(None);
+    };
+    let start_digest = start.as_digest();
+
+    let stale = slot.with_txs(|txs| -> Result<Option<(Vec<BlobStep>, Meetup, Prover)>> {
+        let tx = txs
+            .iter_mut()
+            .find(|tx| tx.blob_tx_hash == *blob_tx_hash)
+            .context("Pending transaction vanished")?;
+        if tx.proven_on.0 == start_digest.0 {
+            return Ok(None);
+        }
+        tx.proven_on = start_digest.clone();
+        let (steps, next) = tx
+            .execute(start)
+            .context("No longer valid after a predecessor failed")?;
+        Ok(Some((steps, next, tx.prover.clone())))
+    })?;
+    let Some((steps, next, prover)) = stale else {
+        return Ok(None);
+    };
+    // Later submissions may go ahead while this one proves
+    drop(slot);
+
+    println!("🔁 Re-proving blob tx {} against the updated state", blob_tx_hash);
+    prove_and_send(host, contract_name, &prover, blob_tx_hash, steps, &NoHooks).await?;
+    Ok(Some(next.as_digest()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contract::MeetupAction;
+    use sdk::{BlobTransaction, ProofTransaction};
+
+    use crate::api::{identity, initial_state, register_contract};
+    use crate::prover::ProverBackend;
+    use crate::simulator::{contract_input, dev_prover, Simulator};
+
+    const CONTRACT: &str = "pending-meetup";
+
+    /// Sends a `PostRoot` blob of `interests` and records it as pending on the
+    /// predicted state, as `submit_actions` does, without proving it yet.
+    async fn send_post_root(host: &str, interests: &str) -> (TxHash, StateDigest) {
+        let client = NodeApiHttpClient::new(host.to_string()).unwrap();
+        let slot = reserve(host, CONTRACT).await;
+        let proven_on = slot.predicted_state(&client).await.unwrap().as_digest();
+        let identity = identity(CONTRACT);
+        let blobs = vec![MeetupAction::PostRoot.as_blob(CONTRACT)];
+        let blob_tx = BlobTransaction::new(identity.clone(), blobs.clone());
+        let blob_tx_hash = client.send_tx_blob(&blob_tx).await.unwrap();
+        slot.push(PendingTx {
+            blob_tx_hash: blob_tx_hash.clone(),
+            identity,
+            blobs,
+            private_inputs: vec![interests.as_bytes().to_vec()],
+            proven_on: proven_on.clone(),
+            prover: dev_prover(),
+        });
+        (blob_tx_hash, proven_on)
+    }
+
+    #[tokio::test]
+    async fn successors_of_a_failed_transaction_are_reproven() {
+        let node = Simulator::for_tests().unwrap();
+        let host = node.url();
+        register_contract(host, CONTRACT).await.unwrap();
+        let (failing, _) = send_post_root(host, "1 9 12 18").await;
+        let (successor, proven_on) = send_post_root(host, "2 9 12 18").await;
+        assert_ne!(proven_on.0, initial_state().as_digest().0);
+
+        // The predecessor's proof reports a failed execution
+        let inputs = contract_input(
+            CONTRACT,
+            &identity(CONTRACT),
+            failing.clone(),
+            &initial_state(),
+            &MeetupAction::PostRoot,
+            b"not interests".to_vec(),
+        );
+        let proof = dev_prover().prove(inputs).await.unwrap();
+        let client = NodeApiHttpClient::new(host.to_string()).unwrap();
+        let proof_tx = ProofTransaction {
+            proof,
+            contract_name: CONTRACT.into(),
+        };
+        client.send_tx_proof(&proof_tx).await.unwrap();
+        assert!(!node.is_settled(&failing));
+
+        // The successor is proven again on the state without the failed root
+        let next = reprove_if_stale(host, CONTRACT, &successor).await.unwrap().unwrap();
+        assert!(node.is_settled(&successor));
+        assert_eq!(node.state(CONTRACT).unwrap().0, next.0);
+        assert_eq!(Meetup::try_from(next).unwrap().merkle_roots.len(), 1);
+        assert!(reprove_if_stale(host, CONTRACT, &successor).await.unwrap().is_none());
+    }
+}
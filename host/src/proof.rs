@@ -0,0 +1,267 @@
+// host/src/proof.rs
+
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use contract::{Ciphertexts, InterestLayout, Meetup, MeetupAction, MeetupEvent, PublicKey};
+use methods::GUEST_ID;
+use risc0_zkvm::Receipt;
+use sdk::{BlobIndex, HyleOutput, ProofData};
+
+use crate::pending::BlobStep;
+use serde::{Deserialize, Serialize};
+
+const BUNDLE_VERSION: u32 = 1;
+
+/// Decodes the Meetup guest's output from `proof`, verifying the receipt first
+/// unless `verify_receipt` is false (for fake receipts, which prove nothing).
+pub fn decode_output(proof: &ProofData, verify_receipt: bool) -> Result<HyleOutput> {
+    let receipt: Receipt = borsh::from_slice(&proof.0).context("Could not decode receipt")?;
+    if verify_receipt {
+        receipt
+            .verify(GUEST_ID)
+            .context("Receipt does not verify against the Meetup guest")?;
+    }
+    receipt.journal.decode().context("Could not decode journal")
+}
+
+/// The event a successful execution reports in its program output; `None`
+/// for a failed one, whose output is the error.
+pub fn decode_event(output: &HyleOutput) -> Result<Option<MeetupEvent>> {
+    if !output.success {
+        return Ok(None);
+    }
+    MeetupEvent::from_program_output(&output.program_outputs)
+        .map(Some)
+        .map_err(anyhow::Error::msg)
+}
+
+/// Checks a proof of one of our own actions before it is sent: it must prove
+/// the transition the host executed locally for `step`, from its initial to
+/// its next state, on the same blob of the same transaction, and a successful one.
+pub fn verify_transition_proof(proof: &ProofData, verify_receipt: bool, step: &BlobStep) -> Result<HyleOutput> {
+    let output = decode_output(proof, verify_receipt)?;
+    ensure!(output.success, "The proven execution failed while the local one succeeded");
+    ensure!(
+        output.initial_state.0 == step.initial_state.0,
+        "The proof starts from another state than the local execution"
+    );
+    ensure!(
+        output.next_state.0 == step.next_state.0,
+        "The proof ends in another state than the local execution"
+    );
+    let inputs = &step.inputs;
+    ensure!(output.tx_hash == inputs.tx_hash, "The proof is about another transaction");
+    ensure!(output.index == inputs.index, "The proof is about another blob");
+    ensure!(output.blobs == sdk::flatten_blobs(&inputs.blobs), "The proof is about other blobs");
+    ensure!(output.identity == inputs.identity, "The proof is for another identity");
+    Ok(output)
+}
+
+/// Checks a client's `AddEncryption` proof before its ciphertexts are used in the PSI.
+///
+/// The proof must be a valid receipt of the Meetup guest, sent as `identity`,
+/// for a single-blob transaction whose blob is exactly
+/// `AddEncryption { public_key, ciphertexts }` in the `Single` layout, and the
+/// execution must have succeeded. The contract only accepts that action when
+/// the ciphertexts encrypt valid catalog answers under the caller's registered
+/// key, so a successful proof vouches for both.
+///
+/// The proven transition must also fit `current`, the on-chain state: it starts
+/// with `public_key` registered for `identity` and ends with the commitment to
+/// `ciphertexts`, and `identity` holds both still.
+pub fn verify_answers_proof(
+    proof: &ProofData,
+    contract_name: &str,
+    identity: &str,
+    public_key: &PublicKey,
+    ciphertexts: &Ciphertexts,
+    current: &Meetup,
+) -> Result<()> {
+    let output = decode_output(proof, true)?;
+
+    ensure!(output.success, "The proven AddEncryption failed");
+    ensure!(
+        output.identity.0 == identity,
+        "The proof was sent as {}, not {}",
+        output.identity.0,
+        identity
+    );
+
+    let expected = MeetupAction::AddEncryption {
+        public_key: *public_key,
+        ciphertexts: ciphertexts.clone(),
+        layout: InterestLayout::Single,
+    };
+    ensure!(
+        output.index == BlobIndex(0) && output.blobs == sdk::flatten_blobs(&[expected.as_blob(contract_name)]),
+        "The proof is not about these ciphertexts"
+    );
+
+    let initial_state: Meetup = borsh::from_slice(&output.initial_state.0).context("Could not decode the initial state")?;
+    let next_state: Meetup = borsh::from_slice(&output.next_state.0).context("Could not decode the next state")?;
+    let commitment = Meetup::commitment(public_key, InterestLayout::Single, ciphertexts);
+    ensure!(
+        initial_state.public_keys.get(identity) == Some(public_key),
+        "The proof starts from a state where {} has another key",
+        identity
+    );
+    ensure!(
+        next_state.commitments.get(identity) == Some(&commitment),
+        "The proof does not end with {} committed to these ciphertexts",
+        identity
+    );
+    ensure!(
+        current.public_keys.get(identity) == Some(public_key) && current.commitments.get(identity) == Some(&commitment),
+        "{} has changed its key or commitment since the proof",
+        identity
+    );
+
+    Ok(())
+}
+
+/// Everything an auditor needs to check a proof without a node, as written by
+/// `export-proof`. Binary fields are hex encoded.
+#[derive(Serialize, Deserialize)]
+pub struct ProofBundle {
+    pub version: u32,
+    /// Image ID of the guest the receipt claims to have run
+    pub image_id: String,
+    /// Borsh encoded receipt
+    pub receipt: String,
+    /// The receipt's journal, as committed by the guest
+    pub journal: String,
+    /// The decoded journal
+    pub public_inputs: HyleOutput,
+}
+
+fn image_id_hex() -> String {
+    hex::encode(sdk::to_u8_array(&GUEST_ID))
+}
+
+/// Bundles `proof` for auditors. The receipt has to verify, so a fake one can't
+/// be passed off for a real one.
+pub fn export_bundle(proof: &ProofData, path: &Path) -> Result<ProofBundle> {
+    let receipt: Receipt = borsh::from_slice(&proof.0).context("Could not decode receipt")?;
+    receipt
+        .verify(GUEST_ID)
+        .context("Receipt does not verify against the Meetup guest")?;
+    let bundle = ProofBundle {
+        version: BUNDLE_VERSION,
+        image_id: image_id_hex(),
+        receipt: hex::encode(&proof.0),
+        journal: hex::encode(&receipt.journal.bytes),
+        public_inputs: receipt.journal.decode().context("Could not decode journal")?,
+    };
+    std::fs::write(path, serde_json::to_vec_pretty(&bundle)?)
+        .with_context(|| format!("Could not write {}", path.display()))?;
+    Ok(bundle)
+}
+
+/// The state transition a bundle proves.
+pub struct VerifiedTransition {
+    pub output: HyleOutput,
+    pub initial_state: Meetup,
+    pub next_state: Meetup,
+}
+
+/// Checks a bundle written by `export_bundle`: the receipt verifies against our
+/// guest and every other field is what the receipt says.
+pub fn verify_bundle(path: &Path) -> Result<VerifiedTransition> {
+    let bytes = std::fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let bundle: ProofBundle = serde_json::from_slice(&bytes).context("Not a proof bundle")?;
+    ensure!(bundle.version == BUNDLE_VERSION, "Unsupported bundle version {}", bundle.version);
+    ensure!(
+        bundle.image_id == image_id_hex(),
+        "The bundle is for image {}, not this Meetup guest ({})",
+        bundle.image_id,
+        image_id_hex()
+    );
+
+    let receipt = hex::decode(&bundle.receipt).context("Invalid receipt encoding")?;
+    let receipt: Receipt = borsh::from_slice(&receipt).context("Could not decode receipt")?;
+    receipt
+        .verify(GUEST_ID)
+        .context("Receipt does not verify against the Meetup guest")?;
+    let output: HyleOutput = receipt.journal.decode().context("Could not decode journal")?;
+    ensure!(
+        hex::decode(&bundle.journal).context("Invalid journal encoding")? == receipt.journal.bytes,
+        "The journal is not the receipt's"
+    );
+    ensure!(
+        serde_json::to_value(&bundle.public_inputs)? == serde_json::to_value(&output)?,
+        "The public inputs are not the receipt's journal"
+    );
+
+    Ok(VerifiedTransition {
+        initial_state: borsh::from_slice(&output.initial_state.0).context("Could not decode the initial state")?,
+        next_state: borsh::from_slice(&output.next_state.0).context("Could not decode the next state")?,
+        output,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prover::{DevModeProver, ProverBackend};
+    use crate::psi::{PAILLIER_P, PAILLIER_Q};
+    use crate::simulator::contract_input;
+
+    #[tokio::test]
+    async fn bundles_verify_until_tampered_with() {
+        // Lets the fake receipt verify
+        std::env::set_var("RISC0_DEV_MODE", "1");
+        let inputs = contract_input(
+            "meetup",
+            "auditor.meetup",
+            sdk::TxHash("00".repeat(32)),
+            &crate::api::initial_state(),
+            &MeetupAction::PostRoot,
+            b"1 9 12 18".to_vec(),
+        );
+        let proof = DevModeProver.prove(inputs).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("meetup-bundle-{}.json", std::process::id()));
+        export_bundle(&proof, &path).unwrap();
+        let transition = verify_bundle(&path).unwrap();
+        assert!(transition.initial_state.merkle_roots.is_empty());
+        assert_eq!(transition.next_state.merkle_roots.len(), 1);
+
+        let mut bundle: ProofBundle = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        bundle.public_inputs.success = false;
+        std::fs::write(&path, serde_json::to_vec(&bundle).unwrap()).unwrap();
+        assert!(verify_bundle(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_proofs_are_bound_to_their_identity_and_state() {
+        std::env::set_var("RISC0_DEV_MODE", "1");
+        let public_key = PublicKey::Paillier(crypto::paillier::prepare_key(PAILLIER_P, PAILLIER_Q).0);
+        let (action, private_input) =
+            crate::api::add_encryption_action(&public_key, InterestLayout::Single, "1 9 12 18").unwrap();
+        let MeetupAction::AddEncryption { ciphertexts, .. } = &action else {
+            unreachable!()
+        };
+        let mut state = crate::api::initial_state();
+        state.public_keys.insert("alice.meetup".into(), public_key);
+        let inputs = contract_input(
+            "meetup",
+            "alice.meetup",
+            sdk::TxHash("00".repeat(32)),
+            &state,
+            &action,
+            private_input,
+        );
+        let proof = DevModeProver.prove(inputs).await.unwrap();
+        let current: Meetup = borsh::from_slice(&decode_output(&proof, true).unwrap().next_state.0).unwrap();
+        verify_answers_proof(&proof, "meetup", "alice.meetup", &public_key, ciphertexts, &current).unwrap();
+
+        // Sent by alice, so it vouches for nobody else's ciphertexts
+        assert!(verify_answers_proof(&proof, "meetup", "bob.meetup", &public_key, ciphertexts, &current).is_err());
+        // Alice revoked her key since
+        let mut revoked = current.clone();
+        revoked.public_keys.remove("alice.meetup");
+        assert!(verify_answers_proof(&proof, "meetup", "alice.meetup", &public_key, ciphertexts, &revoked).is_err());
+    }
+}
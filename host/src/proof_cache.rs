@@ -0,0 +1,135 @@
+// host/src/proof_cache.rs
+
+//! Proofs on disk, keyed by what they prove.
+//!
+//! A proof only depends on the guest and its input, so proving the same
+//! `ContractInput` again, when a failed submission is retried or a pending
+//! transaction is re-sent, can reuse the first receipt. [`CachingProver`] wraps
+//! another backend and stores each of its proofs under the SHA-256 of the
+//! guest's image ID and the input as the guest reads it.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use methods::GUEST_ID;
+use sdk::{ContractInput, ProofData};
+use sha2::{Digest, Sha256};
+
+use crate::proof::decode_output;
+use crate::prover::{Prover, ProverBackend};
+
+pub struct CachingProver {
+    inner: Prover,
+    dir: PathBuf,
+}
+
+impl CachingProver {
+    pub fn new(inner: Prover, dir: impl Into<PathBuf>) -> Self {
+        CachingProver {
+            inner,
+            dir: dir.into(),
+        }
+    }
+
+    fn key(&self, inputs: &ContractInput) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(sdk::to_u8_array(&GUEST_ID));
+        // Fake receipts must not stand in for real ones, nor the other way round
+        hasher.update([self.inner.fakes_receipts() as u8]);
+        for word in risc0_zkvm::serde::to_vec(inputs)? {
+            hasher.update(word.to_le_bytes());
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+#[async_trait]
+impl ProverBackend for CachingProver {
+    async fn prove(&self, inputs: ContractInput) -> Result<ProofData> {
+        let path = self.dir.join(format!("{}.receipt", self.key(&inputs)?));
+        if let Ok(proof) = std::fs::read(&path) {
+            println!("♻️  Reusing the cached proof {}", path.display());
+            return Ok(ProofData(proof));
+        }
+
+        let state = inputs.state.clone();
+        let proof = self.inner.prove(inputs).await?;
+        // Only keep proofs worth submitting, a bad one would otherwise come back on every retry
+        match decode_output(&proof, !self.inner.fakes_receipts()) {
+            Ok(output) if output.success && output.initial_state.0 == state => {}
+            _ => return Ok(proof),
+        }
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Could not create {}", self.dir.display()))?;
+        // Written aside first so a crash never leaves a truncated proof behind
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, &proof.0)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(proof)
+    }
+
+    fn fakes_receipts(&self) -> bool {
+        self.inner.fakes_receipts()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use contract::MeetupAction;
+
+    use crate::prover::DevModeProver;
+    use crate::simulator::contract_input;
+
+    /// Counts the proofs it generates
+    #[derive(Default)]
+    struct CountingProver(AtomicUsize);
+
+    #[async_trait]
+    impl ProverBackend for Arc<CountingProver> {
+        async fn prove(&self, inputs: ContractInput) -> Result<ProofData> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            DevModeProver.prove(inputs).await
+        }
+
+        fn fakes_receipts(&self) -> bool {
+            true
+        }
+    }
+
+    fn inputs(interests: &str) -> ContractInput {
+        contract_input(
+            "meetup",
+            "cache.meetup",
+            sdk::TxHash("11".repeat(32)),
+            &crate::api::initial_state(),
+            &MeetupAction::PostRoot,
+            interests.as_bytes().to_vec(),
+        )
+    }
+
+    #[tokio::test]
+    async fn identical_inputs_are_proven_once() {
+        let dir = std::env::temp_dir().join(format!("meetup-proof-cache-{}", std::process::id()));
+        let counter = Arc::new(CountingProver::default());
+        let prover = CachingProver::new(Arc::new(counter.clone()), &dir);
+
+        let first = prover.prove(inputs("1 9 12 18")).await.unwrap();
+        let again = prover.prove(inputs("1 9 12 18")).await.unwrap();
+        assert_eq!(first.0, again.0);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        // A fresh cache over the same directory still has it
+        let reopened = CachingProver::new(Arc::new(counter.clone()), &dir);
+        reopened.prove(inputs("1 9 12 18")).await.unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        prover.prove(inputs("2 9 12 18")).await.unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
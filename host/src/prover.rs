@@ -0,0 +1,178 @@
+// host/src/prover.rs
+
+//! Where proofs of the Meetup guest come from.
+//!
+//! - [`LocalProver`] proves on this machine's CPU.
+//! - [`DevModeProver`] runs the guest and wraps its journal in a fake receipt.
+//!   Only verifiers running with `RISC0_DEV_MODE=1` accept it, which is what
+//!   tests and local devnets want: the same journal in a fraction of the time.
+//! - [`ExecuteProver`] skips the zkVM altogether: it runs the contract natively,
+//!   as the guest would, and wraps the output in a fake receipt. `--simulate`
+//!   proves with it by default.
+//! - [`RemoteProver`] sends the inputs to a proving service. `serve-prover`
+//!   runs a local stand-in for it on top of one of the other two.
+
+use std::sync::Arc;
+
+use actix_web::{post, web, App, HttpResponse, HttpServer, Responder};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use client_sdk::helpers::risc0::Risc0Prover;
+use contract::Meetup;
+use methods::{GUEST_ELF, GUEST_ID};
+use risc0_zkvm::{default_executor, ExecutorEnv, FakeReceipt, InnerReceipt, Receipt, ReceiptClaim};
+use sdk::{ContractInput, ProofData};
+use serde::{Deserialize, Serialize};
+
+#[async_trait]
+pub trait ProverBackend: Send + Sync {
+    /// Proves the guest's execution of `inputs`.
+    async fn prove(&self, inputs: ContractInput) -> Result<ProofData>;
+
+    /// Whether the receipts are fake, so only their journal can be checked
+    fn fakes_receipts(&self) -> bool {
+        false
+    }
+}
+
+/// The backend the pipeline proves with, shared with `pending` for re-proving
+pub type Prover = Arc<dyn ProverBackend>;
+
+/// Which [`ProverBackend`] a command proves with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProverKind {
+    Local,
+    Dev,
+    Execute,
+    Remote,
+}
+
+impl ProverKind {
+    /// `url` is the proving service of `Remote`.
+    pub fn backend(self, url: Option<&str>) -> Result<Prover> {
+        Ok(match self {
+            ProverKind::Local => Arc::new(LocalProver),
+            ProverKind::Dev => Arc::new(DevModeProver),
+            ProverKind::Execute => Arc::new(ExecuteProver),
+            ProverKind::Remote => Arc::new(RemoteProver::new(url.context("The remote prover needs --prover-url")?)),
+        })
+    }
+}
+
+pub struct LocalProver;
+
+#[async_trait]
+impl ProverBackend for LocalProver {
+    async fn prove(&self, inputs: ContractInput) -> Result<ProofData> {
+        Risc0Prover::new(GUEST_ELF).prove(inputs).await
+    }
+}
+
+pub struct DevModeProver;
+
+#[async_trait]
+impl ProverBackend for DevModeProver {
+    async fn prove(&self, inputs: ContractInput) -> Result<ProofData> {
+        // Executing the guest still takes a while, keep it off the async workers
+        let receipt = tokio::task::spawn_blocking(move || -> Result<Receipt> {
+            let env = ExecutorEnv::builder().write(&inputs)?.build()?;
+            let session = default_executor().execute(env, GUEST_ELF)?;
+            Ok(fake_receipt(GUEST_ID, session.journal.bytes))
+        })
+        .await??;
+        Ok(ProofData(borsh::to_vec(&receipt)?))
+    }
+
+    fn fakes_receipts(&self) -> bool {
+        true
+    }
+}
+
+pub struct ExecuteProver;
+
+#[async_trait]
+impl ProverBackend for ExecuteProver {
+    async fn prove(&self, inputs: ContractInput) -> Result<ProofData> {
+        let (_, output) = sdk::guest::execute::<Meetup>(&inputs);
+        // Laid out as the guest's `env::commit` writes it
+        let words = risc0_zkvm::serde::to_vec(&output)?;
+        let journal = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        Ok(ProofData(borsh::to_vec(&fake_receipt(GUEST_ID, journal))?))
+    }
+
+    fn fakes_receipts(&self) -> bool {
+        true
+    }
+}
+
+/// A receipt that claims the program `image_id` ran successfully and committed `journal`
+pub(crate) fn fake_receipt(image_id: [u32; 8], journal: Vec<u8>) -> Receipt {
+    let claim = ReceiptClaim::ok(image_id, journal.clone());
+    Receipt::new(InnerReceipt::Fake(FakeReceipt::new(claim)), journal)
+}
+
+/// Body of the proving service's `POST /prove`
+#[derive(Serialize, Deserialize)]
+pub struct ProveRequest {
+    pub inputs: ContractInput,
+}
+
+/// Answer of the proving service's `POST /prove`
+#[derive(Serialize, Deserialize)]
+pub struct ProveResponse {
+    /// Hex encoded
+    pub proof: String,
+}
+
+pub struct RemoteProver {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl RemoteProver {
+    pub fn new(url: &str) -> Self {
+        RemoteProver {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ProverBackend for RemoteProver {
+    async fn prove(&self, inputs: ContractInput) -> Result<ProofData> {
+        let url = format!("{}/prove", self.url);
+        let response: ProveResponse = self
+            .client
+            .post(&url)
+            .json(&ProveRequest { inputs })
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("Proving service {} refused the inputs", url))?
+            .json()
+            .await?;
+        Ok(ProofData(hex::decode(response.proof).context("Proving service sent an invalid proof")?))
+    }
+}
+
+#[post("/prove")]
+async fn prove_endpoint(req: web::Json<ProveRequest>, backend: web::Data<dyn ProverBackend>) -> impl Responder {
+    match backend.prove(req.into_inner().inputs).await {
+        Ok(proof) => HttpResponse::Ok().json(ProveResponse {
+            proof: hex::encode(proof.0),
+        }),
+        Err(err) => HttpResponse::UnprocessableEntity().body(format!("{:#}", err)),
+    }
+}
+
+/// Serves `backend` as a proving service on `127.0.0.1:port`, for `RemoteProver`.
+pub async fn serve(backend: Prover, port: u16) -> std::io::Result<()> {
+    println!("Starting proving service on 127.0.0.1:{}", port);
+    let backend = web::Data::from(backend);
+    HttpServer::new(move || App::new().app_data(backend.clone()).service(prove_endpoint))
+        .bind(("127.0.0.1", port))?
+        .run()
+        .await
+}
@@ -0,0 +1,79 @@
+// host/src/psi.rs
+
+use contract::{interest_packing, InterestLayout, PublicKey};
+use crypto::blinding::{blinded_differences, is_unit};
+use crypto::{Blinding, ElGamal, HomomorphicScheme, Paillier};
+use rand::Rng;
+
+use crate::keystore::SecretKey;
+
+/// Demo primes for the Paillier keypair of the cycle benchmark
+pub const PAILLIER_P: u128 = 7759;
+pub const PAILLIER_Q: u128 = 6983;
+
+/// Demo group for exponential ElGamal keys: p = 2^61 - 1, generator 3
+pub const ELGAMAL_P: u128 = 2305843009213693951;
+pub const ELGAMAL_G: u128 = 3;
+
+/// Runs the whole PSI under the keypair's scheme and returns, for each of
+/// Bob's interests, whether Alice holds the same value at that position.
+pub fn find_intersection(
+    public_key: &PublicKey,
+    secret_key: &SecretKey,
+    alice: &[u128],
+    bob: Vec<u128>,
+) -> anyhow::Result<Vec<bool>> {
+    match (public_key, secret_key) {
+        (PublicKey::Paillier(pk), SecretKey::Paillier(sk)) => Ok(run_psi::<Paillier>(pk, sk, alice, bob)),
+        (PublicKey::Elgamal(pk), SecretKey::Elgamal(sk)) => Ok(run_psi::<ElGamal>(pk, sk, alice, bob)),
+        _ => anyhow::bail!("The secret key is not of the public key's scheme"),
+    }
+}
+
+/// Both sides at once, under one keypair: the differences never leave the key
+/// holder, so they go unblinded and Alice's answers are packed densely.
+pub fn run_psi<S: HomomorphicScheme>(pk: &S::PublicKey, sk: &S::SecretKey, alice: &[u128], bob: Vec<u128>) -> Vec<bool> {
+    let packing = InterestLayout::Dense.packing::<S>(pk);
+    let nonces = sample_units::<S>(pk, packing.plaintexts_for(alice.len()));
+    let alice_enc = packing.encrypt::<S>(pk, alice, &nonces);
+
+    let differences = packing.differences::<S>(pk, &alice_enc, &bob);
+    packing.decrypt_matches::<S>(sk, &differences, bob.len())
+}
+
+/// `count` random units modulo `S::scalar_modulus`, e.g. encryption nonces.
+pub fn sample_units<S: HomomorphicScheme>(pk: &S::PublicKey, count: usize) -> Vec<u128> {
+    let modulus = S::scalar_modulus(pk);
+    let mut rng = rand::thread_rng();
+    (0..count)
+        .map(|_| loop {
+            let x = rng.gen_range(1..modulus);
+            if is_unit(x, modulus) {
+                return x;
+            }
+        })
+        .collect()
+}
+
+/// Fresh random blinding factors, one per difference.
+pub fn sample_blindings<S: HomomorphicScheme>(pk: &S::PublicKey, count: usize) -> Vec<Blinding> {
+    let units = sample_units::<S>(pk, 2 * count);
+    units
+        .chunks(2)
+        .map(|pair| Blinding {
+            scalar: pair[0],
+            nonce: pair[1],
+        })
+        .collect()
+}
+
+/// Server side: returns the blinded Enc(x_i - y_i) for each of the client's
+/// ciphertexts, which only tell the client whether `x_i == y_i`.
+pub fn server_code_batch<S: HomomorphicScheme>(
+    y_secret: &[u128],
+    c_x: &[S::Ciphertext],
+    pk: &S::PublicKey,
+    blindings: &[Blinding],
+) -> Vec<S::Ciphertext> {
+    blinded_differences::<S>(pk, &interest_packing(), y_secret, c_x, blindings)
+}
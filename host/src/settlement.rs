@@ -0,0 +1,70 @@
+// host/src/settlement.rs
+
+//! Waiting for a submitted action to settle on the node.
+//!
+//! A blob transaction stays sequenced until its proof is verified or it times
+//! out on the node. The node's indexer API (`/v1/indexer/transaction/hash/...`,
+//! the one `indexer` reads blocks from) reports which, as the transaction's
+//! status.
+//!
+//! While it waits, a transaction whose predecessor failed is proven again
+//! against the state it now starts from (see `pending`).
+
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::time::{sleep, Instant};
+
+use crate::api::Submission;
+use crate::indexer::source::HyleIndexerSource;
+use crate::pending;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Settlement {
+    /// The node applied the proven state transition
+    Settled,
+    /// The blob transaction settled without applying the transition: its
+    /// proof reported a failed execution or it timed out on the node
+    Failed { reason: String },
+    /// Still unsettled when our timeout ran out
+    TimedOut,
+}
+
+/// Polls the node at `host` until `submission`'s blob transaction settles or
+/// fails, or `timeout` runs out.
+pub async fn wait_for_settlement(
+    host: &str,
+    contract_name: &str,
+    submission: &Submission,
+    timeout: Duration,
+) -> Result<Settlement> {
+    let indexer = HyleIndexerSource::new(host);
+    let deadline = Instant::now() + timeout;
+    let blob_tx_hash = &submission.blob_tx_hash;
+    loop {
+        match indexer.transaction_status(&blob_tx_hash.0).await?.as_deref() {
+            Some("Success") => return Ok(Settlement::Settled),
+            Some("Failure") => {
+                return Ok(Settlement::Failed {
+                    reason: format!("Blob tx {} settled as a failure", blob_tx_hash),
+                })
+            }
+            Some("TimedOut") => {
+                return Ok(Settlement::Failed {
+                    reason: format!("Blob tx {} timed out on the node before it was proven", blob_tx_hash),
+                })
+            }
+            // Not indexed yet, or still waiting for its proofs
+            _ => {}
+        }
+        pending::reprove_if_stale(host, contract_name, blob_tx_hash).await?;
+        if Instant::now() >= deadline {
+            return Ok(Settlement::TimedOut);
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
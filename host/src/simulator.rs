@@ -0,0 +1,320 @@
+// host/src/simulator.rs
+
+//! An in-memory Hyle ledger, for `--simulate` and the tests.
+//!
+//! Serves the part of the node's REST API `NodeApiHttpClient` uses, plus the
+//! transaction status of its indexer API, so the rest of the host runs against
+//! it unchanged. Proofs settle as soon as they check
+//! out: the proven next state replaces the contract's once every blob of the
+//! transaction has a proof. Whether receipts are verified is a [`ReceiptCheck`];
+//! either way the journal must start from the contract's state and be about the
+//! transaction's blobs. Aggregates sent to `AGGREGATE_CONTRACT` settle each
+//! proof they fold, in order.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use anyhow::{ensure, Context, Result};
+use risc0_zkvm::sha::Digest;
+use risc0_zkvm::{Journal, Receipt};
+use sdk::api::APIRegisterContract;
+use serde::Serialize;
+use sdk::{BlobIndex, BlobTransaction, Contract, HyleOutput, ProofTransaction, StateDigest, TxHash};
+
+use crate::aggregate::{decode_aggregate, AGGREGATE_CONTRACT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptCheck {
+    /// Only decode the journal, so any receipt passes, real or fake
+    Skip,
+    /// Verify the receipt against the contract's program ID. Fake receipts only
+    /// pass with `RISC0_DEV_MODE=1`.
+    Verify,
+}
+
+/// What the node's indexer reports as a transaction's `transaction_status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+enum TxStatus {
+    /// Waiting for its proofs
+    Sequenced,
+    Success,
+    /// A proof reported a failed execution
+    Failure,
+}
+
+struct UnsettledTx {
+    tx: BlobTransaction,
+    /// Indexes of the blobs proven so far
+    proven: Vec<BlobIndex>,
+}
+
+struct Ledger {
+    receipts: ReceiptCheck,
+    contracts: BTreeMap<String, Contract>,
+    unsettled: HashMap<String, UnsettledTx>,
+    /// Status of every blob transaction
+    statuses: HashMap<String, TxStatus>,
+    next_tx: u64,
+}
+
+impl Ledger {
+    fn new(receipts: ReceiptCheck) -> Self {
+        Ledger {
+            receipts,
+            contracts: BTreeMap::new(),
+            unsettled: HashMap::new(),
+            statuses: HashMap::new(),
+            next_tx: 0,
+        }
+    }
+
+    fn tx_hash(&mut self) -> TxHash {
+        self.next_tx += 1;
+        TxHash(format!("{:064x}", self.next_tx))
+    }
+
+    fn verify(&mut self, proof_tx: &ProofTransaction) -> Result<()> {
+        let receipt: Receipt = borsh::from_slice(&proof_tx.proof.0).context("Could not decode receipt")?;
+        if proof_tx.contract_name.0 == AGGREGATE_CONTRACT {
+            return self.verify_aggregate(&receipt);
+        }
+        let contract = self
+            .contracts
+            .get(&proof_tx.contract_name.0)
+            .context("Unknown contract")?;
+        if self.receipts == ReceiptCheck::Verify {
+            let program_id = Digest::try_from(contract.program_id.0.as_slice()).context("Invalid program ID")?;
+            receipt.verify(program_id).context("Receipt does not verify")?;
+        }
+        let output: HyleOutput = receipt.journal.decode().context("Could not decode journal")?;
+        self.apply(&proof_tx.contract_name.0, output)
+    }
+
+    /// Applies each output folded into an aggregate, in order, as if its proof
+    /// came alone. Each must come from the program of the contract it proves.
+    fn verify_aggregate(&mut self, receipt: &Receipt) -> Result<()> {
+        let aggregator = self
+            .contracts
+            .get(AGGREGATE_CONTRACT)
+            .context("The aggregate contract is not registered")?;
+        if self.receipts == ReceiptCheck::Verify {
+            let program_id = Digest::try_from(aggregator.program_id.0.as_slice()).context("Invalid program ID")?;
+            receipt.verify(program_id).context("Aggregate receipt does not verify")?;
+        }
+        for (image_id, journal) in decode_aggregate(receipt, false)? {
+            let output: HyleOutput = Journal::new(journal).decode().context("Could not decode folded journal")?;
+            let contract_name = self
+                .unsettled
+                .get(&output.tx_hash.0)
+                .and_then(|unsettled| unsettled.tx.blobs.get(output.index.0))
+                .context("No unsettled blob for this folded proof")?
+                .contract_name
+                .0
+                .clone();
+            let contract = self.contracts.get(&contract_name).context("Unknown contract")?;
+            ensure!(
+                contract.program_id.0 == sdk::to_u8_array(&image_id),
+                "A folded proof is of another program than its contract's"
+            );
+            self.apply(&contract_name, output)?;
+        }
+        Ok(())
+    }
+
+    /// Settles the blob `output` proves, for `contract_name`.
+    fn apply(&mut self, contract_name: &str, output: HyleOutput) -> Result<()> {
+        let contract = self.contracts.get(contract_name).context("Unknown contract")?;
+        let unsettled = self
+            .unsettled
+            .get_mut(&output.tx_hash.0)
+            .context("No unsettled blob transaction for this proof")?;
+        let blob = unsettled.tx.blobs.get(output.index.0).context("No such blob")?;
+        ensure!(blob.contract_name.0 == contract_name, "The blob is for another contract");
+        ensure!(output.blobs == sdk::flatten_blobs(&unsettled.tx.blobs), "The proof is about other blobs");
+        ensure!(output.initial_state.0 == contract.state.0, "The proof starts from another state");
+
+        if !output.success {
+            self.unsettled.remove(&output.tx_hash.0);
+            self.statuses.insert(output.tx_hash.0, TxStatus::Failure);
+            return Ok(());
+        }
+        if !unsettled.proven.contains(&output.index) {
+            unsettled.proven.push(output.index);
+        }
+        let settled = unsettled
+            .tx
+            .blobs
+            .iter()
+            .enumerate()
+            .filter(|(_, blob)| self.contracts.contains_key(&blob.contract_name.0))
+            .all(|(i, _)| unsettled.proven.contains(&BlobIndex(i)));
+        if let Some(contract) = self.contracts.get_mut(contract_name) {
+            contract.state = output.next_state;
+        }
+        if settled {
+            self.unsettled.remove(&output.tx_hash.0);
+            self.statuses.insert(output.tx_hash.0, TxStatus::Success);
+        }
+        Ok(())
+    }
+}
+
+type Shared = web::Data<Mutex<Ledger>>;
+
+#[post("/v1/contract/register")]
+async fn register_contract(req: web::Json<APIRegisterContract>, ledger: Shared) -> impl Responder {
+    let req = req.into_inner();
+    let mut ledger = ledger.lock().unwrap();
+    if ledger.contracts.contains_key(&req.contract_name.0) {
+        return HttpResponse::BadRequest().body(format!("Contract {} already exists", req.contract_name.0));
+    }
+    let contract = Contract {
+        name: req.contract_name.clone(),
+        program_id: req.program_id,
+        state: req.state_digest,
+        verifier: req.verifier,
+    };
+    ledger.contracts.insert(req.contract_name.0, contract);
+    let tx_hash = ledger.tx_hash();
+    HttpResponse::Ok().json(tx_hash)
+}
+
+#[get("/v1/contract/{name}")]
+async fn get_contract(name: web::Path<String>, ledger: Shared) -> impl Responder {
+    match ledger.lock().unwrap().contracts.get(name.as_str()) {
+        Some(contract) => HttpResponse::Ok().json(contract),
+        None => HttpResponse::NotFound().body(format!("Contract {} not found", name)),
+    }
+}
+
+#[post("/v1/tx/send/blob")]
+async fn send_blob(tx: web::Json<BlobTransaction>, ledger: Shared) -> impl Responder {
+    let mut ledger = ledger.lock().unwrap();
+    let tx_hash = ledger.tx_hash();
+    ledger.statuses.insert(tx_hash.0.clone(), TxStatus::Sequenced);
+    ledger.unsettled.insert(
+        tx_hash.0.clone(),
+        UnsettledTx {
+            tx: tx.into_inner(),
+            proven: Vec::new(),
+        },
+    );
+    HttpResponse::Ok().json(tx_hash)
+}
+
+#[post("/v1/tx/send/proof")]
+async fn send_proof(tx: web::Json<ProofTransaction>, ledger: Shared) -> impl Responder {
+    let mut ledger = ledger.lock().unwrap();
+    match ledger.verify(&tx) {
+        Ok(()) => {
+            let tx_hash = ledger.tx_hash();
+            HttpResponse::Ok().json(tx_hash)
+        }
+        Err(err) => HttpResponse::BadRequest().body(format!("{:#}", err)),
+    }
+}
+
+/// Only whether it answers 200 matters to the host, so this serves the blob
+/// transaction itself.
+#[get("/v1/unsettled_tx/{hash}")]
+async fn get_unsettled_tx(hash: web::Path<String>, ledger: Shared) -> impl Responder {
+    match ledger.lock().unwrap().unsettled.get(hash.as_str()) {
+        Some(unsettled) => HttpResponse::Ok().json(&unsettled.tx),
+        None => HttpResponse::NotFound().body(format!("No unsettled tx {}", hash)),
+    }
+}
+
+/// The fields of the indexer's transaction the host reads
+#[get("/v1/indexer/transaction/hash/{hash}")]
+async fn get_transaction(hash: web::Path<String>, ledger: Shared) -> impl Responder {
+    match ledger.lock().unwrap().statuses.get(hash.as_str()) {
+        Some(status) => HttpResponse::Ok().json(serde_json::json!({
+            "tx_hash": hash.as_str(),
+            "transaction_status": status,
+        })),
+        None => HttpResponse::NotFound().body(format!("No tx {}", hash)),
+    }
+}
+
+pub struct Simulator {
+    url: String,
+    ledger: Shared,
+}
+
+impl Simulator {
+    /// Serves a fresh ledger on `addr`, e.g. "localhost:4321". Must be called
+    /// from within a Tokio runtime.
+    pub fn start(addr: &str, receipts: ReceiptCheck) -> std::io::Result<Self> {
+        let ledger: Shared = web::Data::new(Mutex::new(Ledger::new(receipts)));
+        let data = ledger.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(register_contract)
+                .service(get_contract)
+                .service(send_blob)
+                .service(send_proof)
+                .service(get_unsettled_tx)
+                .service(get_transaction)
+        })
+        .workers(1)
+        .bind(addr)?;
+        let url = format!("http://{}", server.addrs()[0]);
+        tokio::spawn(server.run());
+        Ok(Simulator { url, ledger })
+    }
+
+    /// A ledger on a free local port that verifies receipts in dev mode, so
+    /// `DevModeProver`'s fake receipts pass and broken ones don't.
+    #[cfg(test)]
+    pub fn for_tests() -> std::io::Result<Self> {
+        std::env::set_var("RISC0_DEV_MODE", "1");
+        Self::start("127.0.0.1:0", ReceiptCheck::Verify)
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Settled state of `contract_name`
+    #[cfg(test)]
+    pub fn state(&self, contract_name: &str) -> Option<StateDigest> {
+        let ledger = self.ledger.lock().unwrap();
+        ledger.contracts.get(contract_name).map(|c| c.state.clone())
+    }
+
+    #[cfg(test)]
+    pub fn is_settled(&self, tx_hash: &TxHash) -> bool {
+        let ledger = self.ledger.lock().unwrap();
+        ledger.statuses.get(&tx_hash.0) == Some(&TxStatus::Success)
+    }
+}
+
+/// The backend tests prove with: fake receipts, which `Simulator::for_tests` accepts
+#[cfg(test)]
+pub fn dev_prover() -> crate::prover::Prover {
+    std::sync::Arc::new(crate::prover::DevModeProver)
+}
+
+/// Guest input of `action` as the only blob of `tx_hash`, sent by `identity`
+/// to `contract_name` on `state`
+#[cfg(test)]
+pub fn contract_input(
+    contract_name: &str,
+    identity: &str,
+    tx_hash: TxHash,
+    state: &contract::Meetup,
+    action: &contract::MeetupAction,
+    private_input: Vec<u8>,
+) -> sdk::ContractInput {
+    sdk::ContractInput {
+        state: state.as_bytes().unwrap(),
+        identity: identity.to_string().into(),
+        tx_hash,
+        private_input,
+        tx_ctx: None,
+        blobs: vec![action.as_blob(contract_name)],
+        index: BlobIndex(0),
+    }
+}
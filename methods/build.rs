@@ -1,3 +1,13 @@
+use std::collections::HashMap;
+
+use risc0_build::GuestOptions;
+
 fn main() {
-    risc0_build::embed_methods();
+    risc0_build::embed_methods_with_options(HashMap::from([
+        ("guest", GuestOptions::default()),
+        // Same guest with the naive `mod_exp`, see `host/src/bench.rs`
+        ("guest-naive", GuestOptions::default()),
+        // Folds guest receipts into one, see `host/src/aggregate.rs`
+        ("aggregator", GuestOptions::default()),
+    ]));
 }